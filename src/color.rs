@@ -0,0 +1,230 @@
+use num_traits::{Float, float::FloatCore};
+use serde_derive::{Deserialize, Serialize};
+
+// D65 white point, used for black and other colors without a defined chromaticity
+pub const WHITE_POINT: Xy = Xy {
+    x: 0.3127,
+    y: 0.3290,
+};
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+// Raw channel values for lights with separate cold and warm white LEDs
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Rgbww {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub cold_white: u8,
+    pub warm_white: u8,
+}
+
+// Hue in degrees [0, 360), saturation and value in [0, 1]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Hsv {
+    pub hue: f32,
+    pub saturation: f32,
+    pub value: f32,
+}
+
+// Color temperature is exchanged in kelvin unless a device declares Mired through NumericState::unit,
+// a Unitless color temperature is kelvin
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Kelvin(pub u32);
+
+// Micro reciprocal degrees, 1,000,000 / kelvin
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Mired(pub u32);
+
+// 1,000,000 / value rounded to the nearest integer, saturating for zero
+fn reciprocal_million(value: u32) -> u32 {
+    match value {
+        0 => u32::MAX,
+        value => (1_000_000 + value / 2) / value,
+    }
+}
+
+impl From<Mired> for Kelvin {
+    fn from(mired: Mired) -> Self {
+        Kelvin(reciprocal_million(mired.0))
+    }
+}
+
+impl From<Kelvin> for Mired {
+    fn from(kelvin: Kelvin) -> Self {
+        Mired(reciprocal_million(kelvin.0))
+    }
+}
+
+impl Kelvin {
+    pub fn to_xy(&self) -> Xy {
+        Xy::from_kelvin(self.0 as f32)
+    }
+}
+
+// CIE 1931 chromaticity coordinates
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Xy {
+    pub x: f32,
+    pub y: f32,
+}
+
+// sRGB transfer functions
+fn to_linear(channel: u8) -> f32 {
+    let c = channel as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        Float::powf((c + 0.055) / 1.055, 2.4)
+    }
+}
+
+fn from_linear(c: f32) -> u8 {
+    let c = if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * Float::powf(c, 1.0 / 2.4) - 0.055
+    };
+    FloatCore::round(c.clamp(0.0, 1.0) * 255.0) as u8
+}
+
+impl Rgb {
+    pub fn to_hsv(&self) -> Hsv {
+        let r = self.r as f32 / 255.0;
+        let g = self.g as f32 / 255.0;
+        let b = self.b as f32 / 255.0;
+
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+
+        let hue = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+
+        Hsv {
+            hue: if hue < 0.0 { hue + 360.0 } else { hue },
+            saturation: if max == 0.0 { 0.0 } else { delta / max },
+            value: max,
+        }
+    }
+
+    pub fn to_xy(&self) -> Xy {
+        let r = to_linear(self.r);
+        let g = to_linear(self.g);
+        let b = to_linear(self.b);
+
+        // sRGB to CIE XYZ
+        let x = 0.4124 * r + 0.3576 * g + 0.1805 * b;
+        let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let z = 0.0193 * r + 0.1192 * g + 0.9505 * b;
+
+        let sum = x + y + z;
+        if sum == 0.0 {
+            return WHITE_POINT;
+        }
+
+        Xy {
+            x: x / sum,
+            y: y / sum,
+        }
+    }
+}
+
+impl Hsv {
+    pub fn to_rgb(&self) -> Rgb {
+        let hue = self.hue % 360.0;
+        let hue = if hue < 0.0 { hue + 360.0 } else { hue } / 60.0;
+        let saturation = self.saturation.clamp(0.0, 1.0);
+        let value = self.value.clamp(0.0, 1.0);
+
+        let chroma = value * saturation;
+        let x = chroma * (1.0 - FloatCore::abs(hue % 2.0 - 1.0));
+        let (r, g, b) = match hue as u8 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+
+        let m = value - chroma;
+        let to_u8 = |c: f32| FloatCore::round((c + m) * 255.0) as u8;
+
+        Rgb {
+            r: to_u8(r),
+            g: to_u8(g),
+            b: to_u8(b),
+        }
+    }
+}
+
+impl Xy {
+    // Brightest sRGB color with this chromaticity, out of gamut colors are clipped
+    pub fn to_rgb(&self) -> Rgb {
+        if self.y <= 0.0 {
+            return WHITE_POINT.to_rgb();
+        }
+
+        // CIE XYZ with Y = 1 to linear sRGB
+        let x = self.x / self.y;
+        let z = (1.0 - self.x - self.y) / self.y;
+
+        let r = (3.2406 * x - 1.5372 - 0.4986 * z).max(0.0);
+        let g = (-0.9689 * x + 1.8758 + 0.0415 * z).max(0.0);
+        let b = (0.0557 * x - 0.2040 + 1.0570 * z).max(0.0);
+
+        let max = r.max(g).max(b);
+        if max == 0.0 {
+            return Rgb { r: 0, g: 0, b: 0 };
+        }
+
+        Rgb {
+            r: from_linear(r / max),
+            g: from_linear(g / max),
+            b: from_linear(b / max),
+        }
+    }
+
+    // Approximate correlated color temperature in kelvin (McCamy's formula)
+    pub fn to_kelvin(&self) -> f32 {
+        let n = (self.x - 0.3320) / (0.1858 - self.y);
+        449.0 * n * n * n + 3525.0 * n * n + 6823.3 * n + 5520.33
+    }
+
+    // Point on the Planckian locus for a color temperature, clamped to 1667K - 25000K
+    pub fn from_kelvin(kelvin: f32) -> Self {
+        // Kim et al. cubic spline fit, evaluated in f64 to keep the published coefficients
+        let t = kelvin.clamp(1667.0, 25000.0) as f64;
+
+        let x = if t <= 4000.0 {
+            -0.2661239e9 / (t * t * t) - 0.2343589e6 / (t * t) + 0.8776956e3 / t + 0.179910
+        } else {
+            -3.0258469e9 / (t * t * t) + 2.1070379e6 / (t * t) + 0.2226347e3 / t + 0.240390
+        };
+
+        let y = if t <= 2222.0 {
+            -1.1063814 * x * x * x - 1.3481102 * x * x + 2.18555832 * x - 0.20219683
+        } else if t <= 4000.0 {
+            -0.9549476 * x * x * x - 1.37418593 * x * x + 2.09137015 * x - 0.16748867
+        } else {
+            3.0817580 * x * x * x - 5.8733867 * x * x + 3.75112997 * x - 0.37001483
+        };
+
+        Self {
+            x: x as f32,
+            y: y as f32,
+        }
+    }
+}
@@ -1,17 +1,80 @@
+use anyhow::{Result, bail};
 use serde_derive::{Deserialize, Serialize};
 
-use crate::device_types::NumericState;
+use crate::{
+    device_types::NumericState,
+    updates::{AttributeUpdate, StateUpdates, UpdateCondition},
+};
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum FanDirection {
     Forward,
     Reverse,
 }
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+impl FanDirection {
+    pub fn reversed(&self) -> Self {
+        match self {
+            FanDirection::Forward => FanDirection::Reverse,
+            FanDirection::Reverse => FanDirection::Forward,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct CeilingFanState {
     pub fan_speed: NumericState,
     pub fan_direction: FanDirection,
     pub light_brightness: NumericState,
-    pub light_color_temp: NumericState,
+    pub light_color_temp: NumericState, // kelvin unless the unit declares Mired
+}
+
+impl CeilingFanState {
+    pub fn apply_update(&mut self, update: &AttributeUpdate) -> Result<()> {
+        match update {
+            AttributeUpdate::FanSpeed(speed) => {
+                self.fan_speed.value = speed.apply_to(&self.fan_speed)
+            }
+            AttributeUpdate::FanDirection(direction) => self.fan_direction = *direction,
+            AttributeUpdate::ToggleFanDirection => {
+                self.fan_direction = self.fan_direction.reversed()
+            }
+            AttributeUpdate::Brightness(brightness) => {
+                self.light_brightness.value = brightness.apply_to(&self.light_brightness)
+            }
+            AttributeUpdate::ColorTemp(color_temp) => {
+                self.light_color_temp.value = color_temp.apply_to(&self.light_color_temp)
+            }
+            _ => bail!("unsupported update for ceiling fan: {:?}", update),
+        }
+
+        Ok(())
+    }
+
+    pub fn diff(&self, other: &Self) -> StateUpdates {
+        let mut updates = StateUpdates::new();
+        if let Some(speed) = self.fan_speed.update_to(&other.fan_speed) {
+            updates.push(AttributeUpdate::FanSpeed(speed));
+        }
+        if self.fan_direction != other.fan_direction {
+            updates.push(AttributeUpdate::FanDirection(other.fan_direction));
+        }
+        if let Some(brightness) = self.light_brightness.update_to(&other.light_brightness) {
+            updates.push(AttributeUpdate::Brightness(brightness));
+        }
+        if let Some(color_temp) = self.light_color_temp.update_to(&other.light_color_temp) {
+            updates.push(AttributeUpdate::ColorTemp(color_temp));
+        }
+        updates
+    }
+
+    pub fn check_condition(&self, condition: &UpdateCondition) -> bool {
+        match condition {
+            UpdateCondition::FanSpeed(speed) => self.fan_speed.value == *speed,
+            UpdateCondition::FanDirection(direction) => self.fan_direction == *direction,
+            UpdateCondition::Brightness(brightness) => self.light_brightness.value == *brightness,
+            UpdateCondition::ColorTemp(color_temp) => self.light_color_temp.value == *color_temp,
+            _ => false,
+        }
+    }
 }
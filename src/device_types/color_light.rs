@@ -1,11 +1,311 @@
+use anyhow::{Context, Result, bail};
 use serde_derive::{Deserialize, Serialize};
 
-use crate::device_types::{NumericState, switch::SwitchPower};
+use crate::{
+    color::{Hsv, Rgbww, Xy},
+    device_types::{NumericState, switch::SwitchPower},
+    updates::{AttributeUpdate, NumericUpdate, StateUpdates, UpdateCondition},
+};
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+// Which of the color light's values is currently driving its output
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ColorMode {
+    #[default]
+    Hs,
+    Xy,
+    ColorTemp,
+    Rgbww,
+}
+
+// Lights that predate color modes only sent power, brightness, hue and saturation, which still
+// deserializes as an hs-only light. The optional values are None when a light lacks that mode.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub struct ColorLightState {
     pub power: SwitchPower,
     pub brightness: NumericState,
     pub hue: NumericState,
     pub saturation: NumericState,
+    #[serde(default)]
+    pub color_mode: ColorMode,
+    #[serde(default)]
+    pub xy: Option<Xy>,
+    #[serde(default)]
+    pub color_temp: Option<NumericState>,
+    #[serde(default)]
+    pub rgbww: Option<Rgbww>,
+}
+
+impl ColorLightState {
+    pub fn apply_update(&mut self, update: &AttributeUpdate) -> Result<()> {
+        match update {
+            AttributeUpdate::Power(power) => self.power = *power,
+            AttributeUpdate::TogglePower => self.power = self.power.toggled(),
+            AttributeUpdate::Brightness(brightness) => {
+                self.brightness.value = brightness.apply_to(&self.brightness)
+            }
+            AttributeUpdate::Hue(hue) => {
+                self.hue.value = hue
+                    .cast::<u32>()
+                    .context("hue out of range")?
+                    .apply_to(&self.hue);
+                self.color_mode = ColorMode::Hs;
+            }
+            AttributeUpdate::Saturation(saturation) => {
+                self.saturation.value = saturation.apply_to(&self.saturation);
+                self.color_mode = ColorMode::Hs;
+            }
+            AttributeUpdate::ColorRgb(rgb) => {
+                self.set_hue_saturation(&rgb.to_hsv());
+                self.color_mode = ColorMode::Hs;
+            }
+            AttributeUpdate::ColorXy(xy) => {
+                // Hue and saturation are kept in sync so hs-only consumers still see the color
+                self.set_hue_saturation(&xy.to_rgb().to_hsv());
+                self.color_mode = match &mut self.xy {
+                    Some(current) => {
+                        *current = *xy;
+                        ColorMode::Xy
+                    }
+                    None => ColorMode::Hs,
+                };
+            }
+            AttributeUpdate::ColorTemp(color_temp_update) => {
+                let color_temp = self
+                    .color_temp
+                    .as_mut()
+                    .context("color light does not support color temperature")?;
+                color_temp.value = color_temp_update.apply_to(color_temp);
+                self.color_mode = ColorMode::ColorTemp;
+            }
+            AttributeUpdate::ColorRgbww(channels) => {
+                let current = self
+                    .rgbww
+                    .as_mut()
+                    .context("color light does not support rgbww")?;
+                *current = *channels;
+                self.color_mode = ColorMode::Rgbww;
+            }
+            _ => bail!("unsupported update for color light: {:?}", update),
+        }
+
+        Ok(())
+    }
+
+    // Scales a color onto this light's hue and saturation ranges
+    pub fn set_hue_saturation(&mut self, hsv: &Hsv) {
+        self.hue.value = NumericUpdate::<u32>::Percent(hsv.hue / 360.0).apply_to(&self.hue);
+        self.saturation.value =
+            NumericUpdate::<u32>::Percent(hsv.saturation).apply_to(&self.saturation);
+    }
+
+    // Every color update also switches the color mode, so the update for the other state's mode
+    // comes last and is sent even if its value is unchanged when the mode differs or an earlier
+    // update switched it. ColorXy also rewrites hue and saturation, so it goes first and they are
+    // sent again after it. In xy mode hue and saturation are derived from the xy value.
+    pub fn diff(&self, other: &Self) -> StateUpdates {
+        let mut updates = StateUpdates::new();
+        if self.power != other.power {
+            updates.push(AttributeUpdate::Power(other.power));
+        }
+        if let Some(brightness) = self.brightness.update_to(&other.brightness) {
+            updates.push(AttributeUpdate::Brightness(brightness));
+        }
+
+        let color_start = updates.len();
+        let modes = [
+            ColorMode::Xy,
+            ColorMode::Hs,
+            ColorMode::ColorTemp,
+            ColorMode::Rgbww,
+        ];
+        for mode in modes.into_iter().filter(|mode| *mode != other.color_mode) {
+            let force = mode == ColorMode::Hs && updates.len() > color_start;
+            self.diff_color(other, mode, force, &mut updates);
+        }
+        self.diff_color(
+            other,
+            other.color_mode,
+            self.color_mode != other.color_mode || updates.len() > color_start,
+            &mut updates,
+        );
+
+        updates
+    }
+
+    fn diff_color(&self, other: &Self, mode: ColorMode, force: bool, updates: &mut StateUpdates) {
+        match mode {
+            ColorMode::Hs if other.color_mode != ColorMode::Xy => {
+                if force || self.hue.value != other.hue.value {
+                    // Hue updates are u16, fall back to a percentage for wider ranges
+                    let hue = NumericUpdate::Absolute(other.hue.value)
+                        .cast::<u16>()
+                        .unwrap_or_else(|| {
+                            let span = other.hue.max.saturating_sub(other.hue.min).max(1);
+                            let offset = other.hue.value.saturating_sub(other.hue.min);
+                            NumericUpdate::Percent((offset as f64 / span as f64) as f32)
+                        });
+                    updates.push(AttributeUpdate::Hue(hue));
+                }
+                if force || self.saturation.value != other.saturation.value {
+                    updates.push(AttributeUpdate::Saturation(NumericUpdate::Absolute(
+                        other.saturation.value,
+                    )));
+                }
+            }
+            ColorMode::Xy => {
+                if let Some(xy) = other.xy
+                    && (force || self.xy != other.xy)
+                {
+                    updates.push(AttributeUpdate::ColorXy(xy));
+                }
+            }
+            ColorMode::ColorTemp => {
+                if let Some(color_temp) = &other.color_temp
+                    && (force || self.color_temp.map(|state| state.value) != Some(color_temp.value))
+                {
+                    updates.push(AttributeUpdate::ColorTemp(NumericUpdate::Absolute(
+                        color_temp.value,
+                    )));
+                }
+            }
+            ColorMode::Rgbww => {
+                if let Some(rgbww) = other.rgbww
+                    && (force || self.rgbww != other.rgbww)
+                {
+                    updates.push(AttributeUpdate::ColorRgbww(rgbww));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn check_condition(&self, condition: &UpdateCondition) -> bool {
+        match condition {
+            UpdateCondition::Power(power) => self.power == *power,
+            UpdateCondition::Brightness(brightness) => self.brightness.value == *brightness,
+            UpdateCondition::Hue(hue) => self.hue.value == *hue,
+            UpdateCondition::Saturation(saturation) => self.saturation.value == *saturation,
+            UpdateCondition::ColorTemp(color_temp) => self
+                .color_temp
+                .is_some_and(|state| state.value == *color_temp),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_types::NumericUnit;
+
+    fn numeric(value: u32, max: u32) -> NumericState {
+        NumericState {
+            value,
+            min: 0,
+            max,
+            step: 1,
+            unit: NumericUnit::Unitless,
+        }
+    }
+
+    fn light(color_mode: ColorMode) -> ColorLightState {
+        ColorLightState {
+            power: SwitchPower::On,
+            brightness: numeric(50, 100),
+            hue: numeric(120, 359),
+            saturation: numeric(80, 100),
+            color_mode,
+            xy: Some(Xy { x: 0.3, y: 0.3 }),
+            color_temp: Some(NumericState {
+                unit: NumericUnit::Kelvin,
+                ..numeric(3000, 6500)
+            }),
+            rgbww: Some(Rgbww {
+                r: 1,
+                g: 2,
+                b: 3,
+                cold_white: 4,
+                warm_white: 5,
+            }),
+        }
+    }
+
+    // Builds the target by applying updates to a, then checks that diffing finds a way back to it
+    fn assert_round_trip(a: ColorLightState, updates: &[AttributeUpdate]) {
+        let mut b = a;
+        for update in updates {
+            b.apply_update(update).unwrap();
+        }
+
+        let mut state = a;
+        for update in &a.diff(&b) {
+            state.apply_update(update).unwrap();
+        }
+        assert_eq!(state, b, "diff was {:?}", a.diff(&b));
+    }
+
+    #[test]
+    fn hs_target_with_only_color_temp_changed() {
+        let a = light(ColorMode::Hs);
+        assert_round_trip(
+            a,
+            &[
+                AttributeUpdate::ColorTemp(NumericUpdate::Absolute(400)),
+                AttributeUpdate::Hue(NumericUpdate::Absolute(120)),
+            ],
+        );
+    }
+
+    #[test]
+    fn background_rgbww_change() {
+        let channels = Rgbww {
+            r: 9,
+            g: 8,
+            b: 7,
+            cold_white: 6,
+            warm_white: 5,
+        };
+
+        for mode in [ColorMode::Hs, ColorMode::ColorTemp, ColorMode::Xy] {
+            let a = light(mode);
+            let restore = match mode {
+                ColorMode::Hs => AttributeUpdate::Saturation(NumericUpdate::Absolute(80)),
+                ColorMode::ColorTemp => AttributeUpdate::ColorTemp(NumericUpdate::Absolute(3000)),
+                _ => AttributeUpdate::ColorXy(Xy { x: 0.3, y: 0.3 }),
+            };
+            assert_round_trip(a, &[AttributeUpdate::ColorRgbww(channels), restore]);
+        }
+    }
+
+    #[test]
+    fn xy_target() {
+        let xy = Xy { x: 0.2, y: 0.5 };
+
+        assert_round_trip(light(ColorMode::Hs), &[AttributeUpdate::ColorXy(xy)]);
+        assert_round_trip(
+            light(ColorMode::Xy),
+            &[
+                AttributeUpdate::ColorTemp(NumericUpdate::Absolute(5000)),
+                AttributeUpdate::ColorXy(Xy { x: 0.3, y: 0.3 }),
+            ],
+        );
+    }
+
+    #[test]
+    fn background_xy_change() {
+        assert_round_trip(
+            light(ColorMode::Hs),
+            &[
+                AttributeUpdate::ColorXy(Xy { x: 0.2, y: 0.5 }),
+                AttributeUpdate::Hue(NumericUpdate::Absolute(120)),
+            ],
+        );
+        assert_round_trip(
+            light(ColorMode::ColorTemp),
+            &[
+                AttributeUpdate::ColorXy(Xy { x: 0.2, y: 0.5 }),
+                AttributeUpdate::ColorTemp(NumericUpdate::Absolute(3000)),
+            ],
+        );
+    }
 }
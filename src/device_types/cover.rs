@@ -0,0 +1,84 @@
+use anyhow::{Context, Result, bail};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    device_types::NumericState,
+    updates::{AttributeUpdate, StateUpdates, UpdateCondition},
+};
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum CoverStatus {
+    Stopped,
+    Opening,
+    Closing,
+    Moving, // adjusting tilt or direction is unknown
+}
+
+// Position min is fully closed and max is fully open. Tilt is None for covers that cannot tilt.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct CoverState {
+    pub position: NumericState,
+    pub tilt: Option<NumericState>,
+    pub status: CoverStatus,
+}
+
+impl CoverState {
+    pub fn apply_update(&mut self, update: &AttributeUpdate) -> Result<()> {
+        match update {
+            AttributeUpdate::CoverPosition(position) => {
+                let target = position.apply_to(&self.position);
+                if target > self.position.value {
+                    self.status = CoverStatus::Opening;
+                } else if target < self.position.value {
+                    self.status = CoverStatus::Closing;
+                }
+                self.position.value = target;
+            }
+            AttributeUpdate::CoverTilt(tilt_update) => {
+                let tilt = self.tilt.as_mut().context("cover does not support tilt")?;
+                tilt.value = tilt_update.apply_to(tilt);
+                self.status = CoverStatus::Moving;
+            }
+            AttributeUpdate::CoverStop => self.status = CoverStatus::Stopped,
+            _ => bail!("unsupported update for cover: {:?}", update),
+        }
+
+        Ok(())
+    }
+
+    pub fn diff(&self, other: &Self) -> StateUpdates {
+        let mut updates = StateUpdates::new();
+        let position = self
+            .position
+            .update_to(&other.position)
+            .map(AttributeUpdate::CoverPosition);
+        let tilt = match (&self.tilt, &other.tilt) {
+            (Some(tilt), Some(other_tilt)) => tilt.update_to(other_tilt),
+            _ => None,
+        }
+        .map(AttributeUpdate::CoverTilt);
+        // The last movement sets the status, tilting reports Moving
+        let movements = if other.status == CoverStatus::Moving {
+            [position, tilt]
+        } else {
+            [tilt, position]
+        };
+        updates.extend(movements.into_iter().flatten());
+        // Moving to a new position or tilt already implies the status, stopping only makes sense
+        // for a cover that stays where it is
+        if updates.is_empty()
+            && other.status == CoverStatus::Stopped
+            && self.status != CoverStatus::Stopped
+        {
+            updates.push(AttributeUpdate::CoverStop);
+        }
+        updates
+    }
+
+    pub fn check_condition(&self, condition: &UpdateCondition) -> bool {
+        match condition {
+            UpdateCondition::CoverPosition(position) => self.position.value == *position,
+            _ => false,
+        }
+    }
+}
@@ -1,9 +1,47 @@
+use anyhow::{Result, bail};
 use serde_derive::{Deserialize, Serialize};
 
-use crate::device_types::{NumericState, switch::SwitchPower};
+use crate::{
+    device_types::{NumericState, switch::SwitchPower},
+    updates::{AttributeUpdate, StateUpdates, UpdateCondition},
+};
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct DimmableLightState {
     pub power: SwitchPower,
     pub brightness: NumericState,
 }
+
+impl DimmableLightState {
+    pub fn apply_update(&mut self, update: &AttributeUpdate) -> Result<()> {
+        match update {
+            AttributeUpdate::Power(power) => self.power = *power,
+            AttributeUpdate::TogglePower => self.power = self.power.toggled(),
+            AttributeUpdate::Brightness(brightness) => {
+                self.brightness.value = brightness.apply_to(&self.brightness)
+            }
+            _ => bail!("unsupported update for dimmable light: {:?}", update),
+        }
+
+        Ok(())
+    }
+
+    pub fn diff(&self, other: &Self) -> StateUpdates {
+        let mut updates = StateUpdates::new();
+        if self.power != other.power {
+            updates.push(AttributeUpdate::Power(other.power));
+        }
+        if let Some(brightness) = self.brightness.update_to(&other.brightness) {
+            updates.push(AttributeUpdate::Brightness(brightness));
+        }
+        updates
+    }
+
+    pub fn check_condition(&self, condition: &UpdateCondition) -> bool {
+        match condition {
+            UpdateCondition::Power(power) => self.power == *power,
+            UpdateCondition::Brightness(brightness) => self.brightness.value == *brightness,
+            _ => false,
+        }
+    }
+}
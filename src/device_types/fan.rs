@@ -0,0 +1,148 @@
+use anyhow::{Context, Result, bail};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    device_types::{
+        NumericState,
+        ceiling_fan::{CeilingFanState, FanDirection},
+    },
+    updates::{AttributeUpdate, StateUpdates, UpdateCondition},
+};
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum FanPreset {
+    Natural,
+    Sleep,
+    Eco,
+    Boost,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct FanLightState {
+    pub brightness: NumericState,
+    pub color_temp: NumericState,
+}
+
+// Standalone fan. Features the fan lacks are None, a ceiling fan is a fan with a light.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct FanState {
+    pub speed: NumericState,
+    pub direction: Option<FanDirection>,
+    pub oscillating: Option<bool>,
+    pub preset: Option<FanPreset>, // None when running at a plain speed
+    pub light: Option<FanLightState>,
+}
+
+impl From<CeilingFanState> for FanState {
+    fn from(state: CeilingFanState) -> Self {
+        Self {
+            speed: state.fan_speed,
+            direction: Some(state.fan_direction),
+            oscillating: None,
+            preset: None,
+            light: Some(FanLightState {
+                brightness: state.light_brightness,
+                color_temp: state.light_color_temp,
+            }),
+        }
+    }
+}
+
+impl FanState {
+    // None if the fan is missing the reversible motor or light a ceiling fan has
+    pub fn to_ceiling_fan(&self) -> Option<CeilingFanState> {
+        let light = self.light?;
+
+        Some(CeilingFanState {
+            fan_speed: self.speed,
+            fan_direction: self.direction?,
+            light_brightness: light.brightness,
+            light_color_temp: light.color_temp,
+        })
+    }
+
+    pub fn apply_update(&mut self, update: &AttributeUpdate) -> Result<()> {
+        match update {
+            AttributeUpdate::FanSpeed(speed) => {
+                self.speed.value = speed.apply_to(&self.speed);
+                self.preset = None;
+            }
+            AttributeUpdate::FanDirection(direction) => {
+                *self
+                    .direction
+                    .as_mut()
+                    .context("fan does not support direction")? = *direction
+            }
+            AttributeUpdate::ToggleFanDirection => {
+                let direction = self
+                    .direction
+                    .as_mut()
+                    .context("fan does not support direction")?;
+                *direction = direction.reversed();
+            }
+            AttributeUpdate::FanOscillation(oscillating) => {
+                *self
+                    .oscillating
+                    .as_mut()
+                    .context("fan does not support oscillation")? = *oscillating
+            }
+            AttributeUpdate::FanPreset(preset) => self.preset = *preset,
+            AttributeUpdate::Brightness(brightness) => {
+                let light = self.light.as_mut().context("fan has no light")?;
+                light.brightness.value = brightness.apply_to(&light.brightness);
+            }
+            AttributeUpdate::ColorTemp(color_temp) => {
+                let light = self.light.as_mut().context("fan has no light")?;
+                light.color_temp.value = color_temp.apply_to(&light.color_temp);
+            }
+            _ => bail!("unsupported update for fan: {:?}", update),
+        }
+
+        Ok(())
+    }
+
+    pub fn diff(&self, other: &Self) -> StateUpdates {
+        let mut updates = StateUpdates::new();
+        let speed = self.speed.update_to(&other.speed);
+        if let Some(speed) = speed {
+            updates.push(AttributeUpdate::FanSpeed(speed));
+        }
+        // Setting the speed clears the preset, so it has to be restored afterwards
+        if self.preset != other.preset || (speed.is_some() && other.preset.is_some()) {
+            updates.push(AttributeUpdate::FanPreset(other.preset));
+        }
+        if let Some(direction) = other.direction
+            && self.direction != other.direction
+        {
+            updates.push(AttributeUpdate::FanDirection(direction));
+        }
+        if let Some(oscillating) = other.oscillating
+            && self.oscillating != other.oscillating
+        {
+            updates.push(AttributeUpdate::FanOscillation(oscillating));
+        }
+        if let (Some(light), Some(other_light)) = (&self.light, &other.light) {
+            if let Some(brightness) = light.brightness.update_to(&other_light.brightness) {
+                updates.push(AttributeUpdate::Brightness(brightness));
+            }
+            if let Some(color_temp) = light.color_temp.update_to(&other_light.color_temp) {
+                updates.push(AttributeUpdate::ColorTemp(color_temp));
+            }
+        }
+        updates
+    }
+
+    pub fn check_condition(&self, condition: &UpdateCondition) -> bool {
+        match condition {
+            UpdateCondition::FanSpeed(speed) => self.speed.value == *speed,
+            UpdateCondition::FanDirection(direction) => self.direction == Some(*direction),
+            UpdateCondition::Brightness(brightness) => self
+                .light
+                .is_some_and(|light| light.brightness.value == *brightness),
+            UpdateCondition::ColorTemp(color_temp) => self
+                .light
+                .is_some_and(|light| light.color_temp.value == *color_temp),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::{Result, bail};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::updates::{AttributeUpdate, StateUpdates, UpdateCondition};
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum GarageDoorStatus {
+    Open,
+    Closed,
+    Opening,
+    Closing,
+    Stopped, // halted somewhere between open and closed
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum GarageDoorCommand {
+    Open,
+    Close,
+    Stop,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ActuationSource {
+    Remote, // through devicectrl
+    WallButton,
+    Manual,
+    Unknown,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct GarageDoorState {
+    pub status: GarageDoorStatus,
+    pub obstructed: bool,
+    pub last_actuation: Option<ActuationSource>,
+    // Asserted by the device when it is unsafe to close remotely, close commands are refused
+    pub interlock: bool,
+}
+
+impl GarageDoorState {
+    pub fn apply_update(&mut self, update: &AttributeUpdate) -> Result<()> {
+        match update {
+            AttributeUpdate::GarageDoor(GarageDoorCommand::Open) => {
+                if self.status != GarageDoorStatus::Open {
+                    self.status = GarageDoorStatus::Opening;
+                }
+            }
+            AttributeUpdate::GarageDoor(GarageDoorCommand::Close) => {
+                if self.interlock {
+                    bail!("garage door safety interlock is set, refusing to close");
+                }
+                if self.obstructed {
+                    bail!("garage door is obstructed, refusing to close");
+                }
+                if self.status != GarageDoorStatus::Closed {
+                    self.status = GarageDoorStatus::Closing;
+                }
+            }
+            AttributeUpdate::GarageDoor(GarageDoorCommand::Stop) => {
+                if matches!(
+                    self.status,
+                    GarageDoorStatus::Opening | GarageDoorStatus::Closing
+                ) {
+                    self.status = GarageDoorStatus::Stopped;
+                }
+            }
+            _ => bail!("unsupported update for garage door: {:?}", update),
+        }
+
+        self.last_actuation = Some(ActuationSource::Remote);
+
+        Ok(())
+    }
+
+    // A door that is already moving toward the target status needs no command
+    pub fn diff(&self, other: &Self) -> StateUpdates {
+        let opening = |status| matches!(status, GarageDoorStatus::Open | GarageDoorStatus::Opening);
+        let closing =
+            |status| matches!(status, GarageDoorStatus::Closed | GarageDoorStatus::Closing);
+
+        let command = match other.status {
+            status if opening(status) && !opening(self.status) => Some(GarageDoorCommand::Open),
+            status if closing(status) && !closing(self.status) => Some(GarageDoorCommand::Close),
+            GarageDoorStatus::Stopped
+                if matches!(
+                    self.status,
+                    GarageDoorStatus::Opening | GarageDoorStatus::Closing
+                ) =>
+            {
+                Some(GarageDoorCommand::Stop)
+            }
+            _ => None,
+        };
+
+        command
+            .map(AttributeUpdate::GarageDoor)
+            .into_iter()
+            .collect()
+    }
+
+    pub fn check_condition(&self, condition: &UpdateCondition) -> bool {
+        match condition {
+            UpdateCondition::GarageDoorStatus(status) => self.status == *status,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use anyhow::{Result, bail};
+use arrayvec::ArrayVec;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    DeviceId, EndpointId,
+    protocol::simple::SIGNATURE_LEN,
+    updates::{AttributeUpdate, StateUpdates, UpdateCondition},
+};
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum LockStatus {
+    Locked,
+    Unlocked,
+    Jammed,
+    Locking,
+    Unlocking,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct LockState {
+    pub status: LockStatus,
+    // Challenge the next Unlock must be authorized for, the device replaces it after every attempt
+    pub unlock_nonce: u32,
+}
+
+const UNLOCK_DOMAIN: &[u8] = b"devicectrl-unlock";
+
+pub const UNLOCK_PAYLOAD_MAX_LEN: usize = UNLOCK_DOMAIN.len()
+    + size_of::<EndpointId>()
+    + DeviceId::new_const().capacity()
+    + size_of::<u32>();
+
+// Bytes covered by an unlock authorization signature: [ domain | u8 endpoint | device id | u32 nonce ]
+// The endpoint comes before the variable length device id so the layout stays unambiguous.
+pub fn unlock_payload(
+    device_id: &DeviceId,
+    endpoint: EndpointId,
+    nonce: u32,
+) -> ArrayVec<u8, UNLOCK_PAYLOAD_MAX_LEN> {
+    let mut payload = ArrayVec::new();
+    payload.extend(UNLOCK_DOMAIN.iter().copied());
+    payload.extend(endpoint.to_be_bytes());
+    payload.extend(device_id.bytes());
+    payload.extend(nonce.to_be_bytes());
+    payload
+}
+
+// Per-command confirmation for unlocking, an ECDSA P-256 signature by the server over
+// unlock_payload for the lock's current unlock_nonce
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct UnlockAuthorization {
+    pub nonce: u32,
+    r: [u8; SIGNATURE_LEN / 2],
+    s: [u8; SIGNATURE_LEN / 2],
+}
+
+impl UnlockAuthorization {
+    pub fn from_signature(nonce: u32, signature: &[u8; SIGNATURE_LEN]) -> Self {
+        let (r, s) = signature.split_at(SIGNATURE_LEN / 2);
+        Self {
+            nonce,
+            r: r.try_into().unwrap_or_default(),
+            s: s.try_into().unwrap_or_default(),
+        }
+    }
+
+    pub fn signature(&self) -> [u8; SIGNATURE_LEN] {
+        let mut signature = [0u8; SIGNATURE_LEN];
+        signature[..SIGNATURE_LEN / 2].copy_from_slice(&self.r);
+        signature[SIGNATURE_LEN / 2..].copy_from_slice(&self.s);
+        signature
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl UnlockAuthorization {
+    pub fn sign(
+        private_key: &p256::ecdsa::SigningKey,
+        device_id: &DeviceId,
+        endpoint: EndpointId,
+        nonce: u32,
+    ) -> Result<Self> {
+        use anyhow::Context;
+        use p256::ecdsa::{Signature, signature::Signer};
+
+        let signature: Signature = private_key
+            .try_sign(&unlock_payload(device_id, endpoint, nonce))
+            .context("failed to sign unlock authorization")?;
+
+        Ok(Self::from_signature(nonce, &signature.to_bytes().into()))
+    }
+}
+
+// Sent from server to lock devices in place of an UpdateCommand, there is no way to unlock
+// through AttributeUpdate alone
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct UnlockCommand {
+    pub device_id: DeviceId,
+    #[serde(default)]
+    pub endpoint: EndpointId,
+    pub authorization: UnlockAuthorization,
+}
+
+impl LockState {
+    pub fn apply_update(&mut self, update: &AttributeUpdate) -> Result<()> {
+        match update {
+            AttributeUpdate::Lock => self.status = LockStatus::Locking,
+            AttributeUpdate::Unlock => {
+                bail!("unlock requires an authorized UnlockCommand")
+            }
+            _ => bail!("unsupported update for lock: {:?}", update),
+        }
+
+        Ok(())
+    }
+
+    // Verifies the authorization against the current challenge with the device's crypto backend,
+    // then replaces the challenge with next_nonce whether or not the attempt succeeded. The payload
+    // is built from the lock's own id and endpoint, so an authorization signed for another lock or
+    // endpoint never verifies.
+    pub fn apply_unlock(
+        &mut self,
+        device_id: &DeviceId,
+        endpoint: EndpointId,
+        command: &UnlockCommand,
+        next_nonce: u32,
+        verify: impl FnOnce(&[u8], &[u8; SIGNATURE_LEN]) -> Result<bool>,
+    ) -> Result<()> {
+        let authorization = &command.authorization;
+        let expected_nonce = self.unlock_nonce;
+        self.unlock_nonce = next_nonce;
+
+        if command.device_id != *device_id || command.endpoint != endpoint {
+            bail!(
+                "unlock command is for another lock! expected={}/{}, got={}/{}",
+                device_id,
+                endpoint,
+                command.device_id,
+                command.endpoint
+            );
+        }
+
+        if authorization.nonce != expected_nonce {
+            bail!(
+                "unlock authorization is stale! expected={}, got={}",
+                expected_nonce,
+                authorization.nonce
+            );
+        }
+
+        let payload = unlock_payload(device_id, endpoint, expected_nonce);
+        if !verify(&payload, &authorization.signature())? {
+            bail!("unlock authorization signature does not match!");
+        }
+
+        self.status = LockStatus::Unlocking;
+        Ok(())
+    }
+
+    // The unlock nonce is a challenge and not compared. Unlocking diffs to the Unlock request, which
+    // the server has to turn into an UnlockCommand.
+    pub fn diff(&self, other: &Self) -> StateUpdates {
+        let locking = |status| matches!(status, LockStatus::Locked | LockStatus::Locking);
+        let unlocking = |status| matches!(status, LockStatus::Unlocked | LockStatus::Unlocking);
+
+        let update = match other.status {
+            status if locking(status) && !locking(self.status) => Some(AttributeUpdate::Lock),
+            status if unlocking(status) && !unlocking(self.status) => Some(AttributeUpdate::Unlock),
+            _ => None,
+        };
+
+        update.into_iter().collect()
+    }
+
+    pub fn check_condition(&self, condition: &UpdateCondition) -> bool {
+        match condition {
+            UpdateCondition::LockStatus(status) => self.status == *status,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Stand-in for ECDSA, the signature is the payload padded with zeros
+    fn fake_sign(device_id: &DeviceId, endpoint: EndpointId, nonce: u32) -> UnlockCommand {
+        let mut signature = [0u8; SIGNATURE_LEN];
+        let payload = unlock_payload(device_id, endpoint, nonce);
+        signature[..payload.len()].copy_from_slice(&payload);
+
+        UnlockCommand {
+            device_id: *device_id,
+            endpoint,
+            authorization: UnlockAuthorization::from_signature(nonce, &signature),
+        }
+    }
+
+    fn fake_verify(payload: &[u8], signature: &[u8; SIGNATURE_LEN]) -> Result<bool> {
+        Ok(signature[..payload.len()] == *payload
+            && signature[payload.len()..].iter().all(|byte| *byte == 0))
+    }
+
+    fn locked() -> LockState {
+        LockState {
+            status: LockStatus::Locked,
+            unlock_nonce: 7,
+        }
+    }
+
+    fn front_door() -> DeviceId {
+        DeviceId::from("front-door").unwrap()
+    }
+
+    #[test]
+    fn valid_authorization_unlocks() {
+        let mut lock = locked();
+        let command = fake_sign(&front_door(), 1, 7);
+
+        lock.apply_unlock(&front_door(), 1, &command, 8, fake_verify)
+            .unwrap();
+        assert_eq!(lock.status, LockStatus::Unlocking);
+        assert_eq!(lock.unlock_nonce, 8);
+    }
+
+    #[test]
+    fn stale_nonce_is_rejected() {
+        let mut lock = locked();
+        let command = fake_sign(&front_door(), 0, 6);
+
+        assert!(
+            lock.apply_unlock(&front_door(), 0, &command, 8, fake_verify)
+                .is_err()
+        );
+        assert_eq!(lock.status, LockStatus::Locked);
+        assert_eq!(lock.unlock_nonce, 8);
+    }
+
+    #[test]
+    fn bad_signature_is_rejected() {
+        let mut lock = locked();
+        let mut command = fake_sign(&front_door(), 0, 7);
+        let mut signature = command.authorization.signature();
+        signature[SIGNATURE_LEN - 1] = 1;
+        command.authorization = UnlockAuthorization::from_signature(7, &signature);
+
+        assert!(
+            lock.apply_unlock(&front_door(), 0, &command, 8, fake_verify)
+                .is_err()
+        );
+        assert_eq!(lock.status, LockStatus::Locked);
+        assert_eq!(lock.unlock_nonce, 8);
+    }
+
+    #[test]
+    fn authorization_for_another_lock_is_rejected() {
+        let back_door = DeviceId::from("back-door").unwrap();
+        let mut lock = locked();
+
+        // Signed for the other lock and sent as such
+        let command = fake_sign(&back_door, 0, 7);
+        assert!(
+            lock.apply_unlock(&front_door(), 0, &command, 8, fake_verify)
+                .is_err()
+        );
+        assert_eq!(lock.unlock_nonce, 8);
+
+        // Signed for the other lock but addressed to this one
+        let command = UnlockCommand {
+            device_id: front_door(),
+            ..fake_sign(&back_door, 0, 8)
+        };
+        assert!(
+            lock.apply_unlock(&front_door(), 0, &command, 9, fake_verify)
+                .is_err()
+        );
+        assert_eq!(lock.status, LockStatus::Locked);
+        assert_eq!(lock.unlock_nonce, 9);
+    }
+
+    #[test]
+    fn authorization_for_another_endpoint_is_rejected() {
+        let mut lock = locked();
+
+        let command = fake_sign(&front_door(), 1, 7);
+        assert!(
+            lock.apply_unlock(&front_door(), 0, &command, 8, fake_verify)
+                .is_err()
+        );
+        assert_eq!(lock.unlock_nonce, 8);
+
+        let command = UnlockCommand {
+            endpoint: 0,
+            ..fake_sign(&front_door(), 1, 8)
+        };
+        assert!(
+            lock.apply_unlock(&front_door(), 0, &command, 9, fake_verify)
+                .is_err()
+        );
+        assert_eq!(lock.status, LockStatus::Locked);
+        assert_eq!(lock.unlock_nonce, 9);
+    }
+
+    #[test]
+    fn authorization_is_single_use() {
+        let mut lock = locked();
+        let command = fake_sign(&front_door(), 0, 7);
+
+        lock.apply_unlock(&front_door(), 0, &command, 8, fake_verify)
+            .unwrap();
+        lock.status = LockStatus::Locked;
+        assert!(
+            lock.apply_unlock(&front_door(), 0, &command, 9, fake_verify)
+                .is_err()
+        );
+        assert_eq!(lock.status, LockStatus::Locked);
+        assert_eq!(lock.unlock_nonce, 9);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn p256_authorization_unlocks() {
+        use p256::ecdsa::{Signature, SigningKey, VerifyingKey, signature::Verifier};
+
+        let private_key = SigningKey::from_slice(&[0x42; 32]).unwrap();
+        let public_key = VerifyingKey::from(&private_key);
+        let verify = |payload: &[u8], signature: &[u8; SIGNATURE_LEN]| {
+            let signature = Signature::from_slice(signature)?;
+            Ok(public_key.verify(payload, &signature).is_ok())
+        };
+
+        let mut lock = locked();
+        let command = UnlockCommand {
+            device_id: front_door(),
+            endpoint: 2,
+            authorization: UnlockAuthorization::sign(&private_key, &front_door(), 2, 7).unwrap(),
+        };
+        lock.apply_unlock(&front_door(), 2, &command, 8, verify)
+            .unwrap();
+        assert_eq!(lock.status, LockStatus::Unlocking);
+
+        let mut other = locked();
+        assert!(
+            other
+                .apply_unlock(&front_door(), 3, &command, 8, verify)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,94 @@
+use anyhow::{Result, bail};
+use arrayvec::ArrayString;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    device_types::NumericState,
+    updates::{AttributeUpdate, StateUpdates, UpdateCondition},
+};
+
+pub type MediaSource = ArrayString<24>;
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum PlaybackStatus {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum MediaControl {
+    Play,
+    Pause,
+    Stop,
+    Next,
+    Previous,
+}
+
+// Longer values are truncated by the device
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct TrackMetadata {
+    pub title: ArrayString<48>,
+    pub artist: ArrayString<32>,
+    pub album: ArrayString<32>,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct MediaPlayerState {
+    pub playback: PlaybackStatus,
+    pub volume: NumericState,
+    pub muted: bool,
+    pub source: Option<MediaSource>,
+    pub track: Option<TrackMetadata>,
+}
+
+impl MediaPlayerState {
+    pub fn apply_update(&mut self, update: &AttributeUpdate) -> Result<()> {
+        match update {
+            AttributeUpdate::MediaControl(control) => match control {
+                MediaControl::Play => self.playback = PlaybackStatus::Playing,
+                MediaControl::Pause => self.playback = PlaybackStatus::Paused,
+                MediaControl::Stop => self.playback = PlaybackStatus::Stopped,
+                // The device reports the new track once it has changed
+                MediaControl::Next | MediaControl::Previous => {}
+            },
+            AttributeUpdate::Volume(volume) => self.volume.value = volume.apply_to(&self.volume),
+            AttributeUpdate::Mute(muted) => self.muted = *muted,
+            AttributeUpdate::SelectSource(source) => self.source = Some(*source),
+            _ => bail!("unsupported update for media player: {:?}", update),
+        }
+
+        Ok(())
+    }
+
+    // The track is chosen by the device, so it is not compared
+    pub fn diff(&self, other: &Self) -> StateUpdates {
+        let mut updates = StateUpdates::new();
+        if self.playback != other.playback {
+            updates.push(AttributeUpdate::MediaControl(match other.playback {
+                PlaybackStatus::Playing => MediaControl::Play,
+                PlaybackStatus::Paused => MediaControl::Pause,
+                PlaybackStatus::Stopped => MediaControl::Stop,
+            }));
+        }
+        if let Some(volume) = self.volume.update_to(&other.volume) {
+            updates.push(AttributeUpdate::Volume(volume));
+        }
+        if self.muted != other.muted {
+            updates.push(AttributeUpdate::Mute(other.muted));
+        }
+        if let Some(source) = other.source
+            && self.source != other.source
+        {
+            updates.push(AttributeUpdate::SelectSource(source));
+        }
+        updates
+    }
+
+    pub fn check_condition(&self, condition: &UpdateCondition) -> bool {
+        match condition {
+            UpdateCondition::Playback(playback) => self.playback == *playback,
+            _ => false,
+        }
+    }
+}
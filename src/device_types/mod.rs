@@ -1,23 +1,63 @@
+use num_traits::{NumCast, cast};
 use serde_derive::{Deserialize, Serialize};
 
+use crate::{
+    color::{Kelvin, Mired},
+    updates::NumericUpdate,
+};
+
 pub mod ceiling_fan;
 pub mod color_light;
+pub mod cover;
 pub mod dimmable_light;
+pub mod fan;
+pub mod garage_door;
+pub mod lock;
+pub mod media_player;
+pub mod sensor;
 pub mod switch;
+pub mod thermostat;
+pub mod tunable_white_light;
+pub mod valve;
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct NumericState<T: Copy = u32> {
     pub value: T,
     pub min: T,
     pub max: T,
     pub step: T,
+    #[serde(default, skip_serializing_if = "NumericUnit::is_unitless")]
+    pub unit: NumericUnit,
+}
+
+impl<T: Copy + PartialEq> NumericState<T> {
+    // Absolute update to the target's value, None if the value is unchanged
+    pub fn update_to(&self, target: &Self) -> Option<NumericUpdate<T>> {
+        (self.value != target.value).then_some(NumericUpdate::Absolute(target.value))
+    }
 }
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+// Physical unit of a numeric attribute, so servers can translate between device ranges
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum NumericUnit {
+    #[default]
+    Unitless, // device-specific scale, except for color temperatures which are then in kelvin
+    Percent,
+    Kelvin,
+    Mired,
+    CentiCelsius,
+    #[serde(other)]
+    Unknown, // unit from a newer peer, never converted
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct NumericProperties<T: Copy = u32> {
     pub min: T,
     pub max: T,
     pub step: T,
+    #[serde(default, skip_serializing_if = "NumericUnit::is_unitless")]
+    pub unit: NumericUnit,
 }
 
 impl<T: Copy> NumericProperties<T> {
@@ -27,6 +67,52 @@ impl<T: Copy> NumericProperties<T> {
             min: self.min,
             max: self.max,
             step: self.step,
+            unit: self.unit,
         }
     }
 }
+
+impl NumericUnit {
+    pub fn is_unitless(&self) -> bool {
+        *self == NumericUnit::Unitless
+    }
+
+    // Converts a color temperature into this unit, None if the unit is not a color temperature.
+    // Color temperatures without a declared unit are kelvin.
+    pub fn color_temp_from_kelvin<T: NumCast>(&self, kelvin: Kelvin) -> Option<T> {
+        match self {
+            NumericUnit::Unitless | NumericUnit::Kelvin => cast(kelvin.0),
+            NumericUnit::Mired => cast(Mired::from(kelvin).0),
+            _ => None,
+        }
+    }
+
+    pub fn color_temp_to_kelvin<T: NumCast>(&self, value: T) -> Option<Kelvin> {
+        match self {
+            NumericUnit::Unitless | NumericUnit::Kelvin => cast(value).map(Kelvin),
+            NumericUnit::Mired => cast(value).map(|mired| Mired(mired).into()),
+            _ => None,
+        }
+    }
+}
+
+impl<T: Copy + NumCast> NumericProperties<T> {
+    pub fn color_temp_from_kelvin(&self, kelvin: Kelvin) -> Option<T> {
+        self.unit.color_temp_from_kelvin(kelvin)
+    }
+
+    pub fn color_temp_to_kelvin(&self, value: T) -> Option<Kelvin> {
+        self.unit.color_temp_to_kelvin(value)
+    }
+}
+
+impl<T: Copy + NumCast> NumericState<T> {
+    // Lets receivers translate color temperatures between devices that use different units
+    pub fn color_temp_kelvin(&self) -> Option<Kelvin> {
+        self.unit.color_temp_to_kelvin(self.value)
+    }
+
+    pub fn color_temp_from_kelvin(&self, kelvin: Kelvin) -> Option<T> {
+        self.unit.color_temp_from_kelvin(kelvin)
+    }
+}
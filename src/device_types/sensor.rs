@@ -0,0 +1,57 @@
+use anyhow::{Result, bail};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    device_types::thermostat::CentiCelsius,
+    updates::{AttributeUpdate, StateUpdates, UpdateCondition},
+};
+
+// Milliseconds since the unix epoch
+pub type Timestamp = u64;
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct SensorReading<T: Copy> {
+    pub value: T,
+    // None when the device has no wall clock, receivers should use the time the reading arrived
+    #[serde(default)]
+    pub timestamp: Option<Timestamp>,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ContactState {
+    Open,
+    Closed,
+}
+
+// Read-only device reporting readings through UpdateNotification. Readings the board cannot
+// measure are None and left out of the serialized state.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct SensorState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<SensorReading<CentiCelsius>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub humidity: Option<SensorReading<u16>>, // relative humidity in hundredths of a percent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub illuminance: Option<SensorReading<u32>>, // lux
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub motion: Option<SensorReading<bool>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub contact: Option<SensorReading<ContactState>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub air_quality: Option<SensorReading<u16>>, // US AQI
+}
+
+impl SensorState {
+    pub fn apply_update(&mut self, update: &AttributeUpdate) -> Result<()> {
+        bail!("sensors are read-only: {:?}", update)
+    }
+
+    // Readings cannot be updated, so there is never anything to report
+    pub fn diff(&self, _other: &Self) -> StateUpdates {
+        StateUpdates::new()
+    }
+
+    pub fn check_condition(&self, _condition: &UpdateCondition) -> bool {
+        false
+    }
+}
@@ -1,12 +1,64 @@
+use anyhow::{Result, bail};
 use serde_derive::{Deserialize, Serialize};
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+use crate::updates::{AttributeUpdate, StateUpdates, UpdateCondition};
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum SwitchPower {
     On,
     Off,
 }
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+impl SwitchPower {
+    pub fn toggled(&self) -> Self {
+        match self {
+            SwitchPower::On => SwitchPower::Off,
+            SwitchPower::Off => SwitchPower::On,
+        }
+    }
+}
+
+// Readings from switches that can measure their load. Integer fixed-point units keep
+// notifications short compared to serialized floats.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct PowerMetering {
+    pub power_mw: u32,
+    pub voltage_mv: u32,
+    pub current_ma: u32,
+    pub energy_wh: u32, // cumulative since the device started counting
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct SwitchState {
     pub power: SwitchPower,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metering: Option<PowerMetering>,
+}
+
+impl SwitchState {
+    pub fn apply_update(&mut self, update: &AttributeUpdate) -> Result<()> {
+        match update {
+            AttributeUpdate::Power(power) => self.power = *power,
+            AttributeUpdate::TogglePower => self.power = self.power.toggled(),
+            _ => bail!("unsupported update for switch: {:?}", update),
+        }
+
+        Ok(())
+    }
+
+    // Metering is measured by the device and has no update, so only power is compared
+    pub fn diff(&self, other: &Self) -> StateUpdates {
+        let mut updates = StateUpdates::new();
+        if self.power != other.power {
+            updates.push(AttributeUpdate::Power(other.power));
+        }
+        updates
+    }
+
+    pub fn check_condition(&self, condition: &UpdateCondition) -> bool {
+        match condition {
+            UpdateCondition::Power(power) => self.power == *power,
+            _ => false,
+        }
+    }
 }
@@ -0,0 +1,104 @@
+use anyhow::{Result, bail};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    device_types::NumericState,
+    updates::{AttributeUpdate, StateUpdates, UpdateCondition},
+};
+
+// Fixed-point temperature in hundredths of a degree Celsius
+pub type CentiCelsius = i32;
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum HvacMode {
+    Off,
+    Heat,
+    Cool,
+    Auto,
+    FanOnly,
+}
+
+// What the equipment is currently doing, reported by the device
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum HvacAction {
+    Off,
+    Idle,
+    Heating,
+    Cooling,
+    Fan,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct ThermostatState {
+    pub current_temperature: CentiCelsius,
+    pub heat_setpoint: NumericState<CentiCelsius>,
+    pub cool_setpoint: NumericState<CentiCelsius>,
+    pub mode: HvacMode,
+    pub action: HvacAction,
+}
+
+impl ThermostatState {
+    pub fn apply_update(&mut self, update: &AttributeUpdate) -> Result<()> {
+        let (heat, cool) = match update {
+            AttributeUpdate::HeatSetpoint(setpoint) => (
+                setpoint.apply_to(&self.heat_setpoint),
+                self.cool_setpoint.value,
+            ),
+            AttributeUpdate::CoolSetpoint(setpoint) => (
+                self.heat_setpoint.value,
+                setpoint.apply_to(&self.cool_setpoint),
+            ),
+            AttributeUpdate::HvacMode(mode) => {
+                self.mode = *mode;
+                return Ok(());
+            }
+            _ => bail!("unsupported update for thermostat: {:?}", update),
+        };
+
+        if heat > cool {
+            bail!(
+                "heat setpoint {} must not exceed cool setpoint {}",
+                heat,
+                cool
+            );
+        }
+
+        self.heat_setpoint.value = heat;
+        self.cool_setpoint.value = cool;
+
+        Ok(())
+    }
+
+    // Setpoints are ordered so the heat setpoint never exceeds the cool setpoint in between.
+    // The current temperature and action are measured by the device and have no update.
+    pub fn diff(&self, other: &Self) -> StateUpdates {
+        let mut updates = StateUpdates::new();
+        if self.mode != other.mode {
+            updates.push(AttributeUpdate::HvacMode(other.mode));
+        }
+
+        let heat = self
+            .heat_setpoint
+            .update_to(&other.heat_setpoint)
+            .map(AttributeUpdate::HeatSetpoint);
+        let cool = self
+            .cool_setpoint
+            .update_to(&other.cool_setpoint)
+            .map(AttributeUpdate::CoolSetpoint);
+        let setpoints = if other.heat_setpoint.value > self.cool_setpoint.value {
+            [cool, heat]
+        } else {
+            [heat, cool]
+        };
+        updates.extend(setpoints.into_iter().flatten());
+
+        updates
+    }
+
+    pub fn check_condition(&self, condition: &UpdateCondition) -> bool {
+        match condition {
+            UpdateCondition::HvacMode(mode) => self.mode == *mode,
+            _ => false,
+        }
+    }
+}
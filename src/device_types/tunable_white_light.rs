@@ -0,0 +1,55 @@
+use anyhow::{Result, bail};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    device_types::{NumericState, switch::SwitchPower},
+    updates::{AttributeUpdate, StateUpdates, UpdateCondition},
+};
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct TunableWhiteLightState {
+    pub power: SwitchPower,
+    pub brightness: NumericState,
+    pub color_temp: NumericState,
+}
+
+impl TunableWhiteLightState {
+    pub fn apply_update(&mut self, update: &AttributeUpdate) -> Result<()> {
+        match update {
+            AttributeUpdate::Power(power) => self.power = *power,
+            AttributeUpdate::TogglePower => self.power = self.power.toggled(),
+            AttributeUpdate::Brightness(brightness) => {
+                self.brightness.value = brightness.apply_to(&self.brightness)
+            }
+            AttributeUpdate::ColorTemp(color_temp) => {
+                self.color_temp.value = color_temp.apply_to(&self.color_temp)
+            }
+            _ => bail!("unsupported update for tunable white light: {:?}", update),
+        }
+
+        Ok(())
+    }
+
+    pub fn diff(&self, other: &Self) -> StateUpdates {
+        let mut updates = StateUpdates::new();
+        if self.power != other.power {
+            updates.push(AttributeUpdate::Power(other.power));
+        }
+        if let Some(brightness) = self.brightness.update_to(&other.brightness) {
+            updates.push(AttributeUpdate::Brightness(brightness));
+        }
+        if let Some(color_temp) = self.color_temp.update_to(&other.color_temp) {
+            updates.push(AttributeUpdate::ColorTemp(color_temp));
+        }
+        updates
+    }
+
+    pub fn check_condition(&self, condition: &UpdateCondition) -> bool {
+        match condition {
+            UpdateCondition::Power(power) => self.power == *power,
+            UpdateCondition::Brightness(brightness) => self.brightness.value == *brightness,
+            UpdateCondition::ColorTemp(color_temp) => self.color_temp.value == *color_temp,
+            _ => false,
+        }
+    }
+}
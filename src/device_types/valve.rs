@@ -0,0 +1,88 @@
+use anyhow::{Result, bail};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    device_types::sensor::SensorReading,
+    updates::{AttributeUpdate, StateUpdates, UpdateCondition},
+};
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ValveStatus {
+    Open,
+    Closed,
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ValveCommand {
+    Open,
+    Close,
+    OpenFor(u32), // seconds, enforced by the device even if the server connection drops
+}
+
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct ValveState {
+    pub status: ValveStatus,
+    pub remaining_secs: Option<u32>, // None when open without a time limit or closed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flow: Option<SensorReading<u32>>, // milliliters per minute
+}
+
+impl ValveState {
+    pub fn apply_update(&mut self, update: &AttributeUpdate) -> Result<()> {
+        match update {
+            AttributeUpdate::Valve(ValveCommand::Open) => {
+                self.status = ValveStatus::Open;
+                self.remaining_secs = None;
+            }
+            AttributeUpdate::Valve(ValveCommand::Close) => self.close(),
+            AttributeUpdate::Valve(ValveCommand::OpenFor(0)) => self.close(),
+            AttributeUpdate::Valve(ValveCommand::OpenFor(secs)) => {
+                self.status = ValveStatus::Open;
+                self.remaining_secs = Some(*secs);
+            }
+            _ => bail!("unsupported update for valve: {:?}", update),
+        }
+
+        Ok(())
+    }
+
+    fn close(&mut self) {
+        self.status = ValveStatus::Closed;
+        self.remaining_secs = None;
+    }
+
+    // Called by the device from its local timer, closes the valve once the run time is over.
+    // Returns true if the state changed and a notification should be sent.
+    pub fn tick(&mut self, elapsed_secs: u32) -> bool {
+        let Some(remaining) = self.remaining_secs else {
+            return false;
+        };
+
+        match remaining.saturating_sub(elapsed_secs) {
+            0 => self.close(),
+            left if left == remaining => return false,
+            left => self.remaining_secs = Some(left),
+        }
+
+        true
+    }
+
+    // A different remaining run time is reported as a new OpenFor, flow is measured and not compared
+    pub fn diff(&self, other: &Self) -> StateUpdates {
+        let command = match (other.status, other.remaining_secs) {
+            _ if self.status == other.status && self.remaining_secs == other.remaining_secs => None,
+            (ValveStatus::Closed, _) => Some(ValveCommand::Close),
+            (ValveStatus::Open, Some(secs)) => Some(ValveCommand::OpenFor(secs)),
+            (ValveStatus::Open, None) => Some(ValveCommand::Open),
+        };
+
+        command.map(AttributeUpdate::Valve).into_iter().collect()
+    }
+
+    pub fn check_condition(&self, condition: &UpdateCondition) -> bool {
+        match condition {
+            UpdateCondition::ValveStatus(status) => self.status == *status,
+            _ => false,
+        }
+    }
+}
@@ -1,16 +1,27 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
+use anyhow::{Result, bail};
 use arrayvec::ArrayString;
 use device_types::{
-    ceiling_fan::CeilingFanState, color_light::ColorLightState, dimmable_light::DimmableLightState,
-    switch::SwitchState,
+    ceiling_fan::CeilingFanState, color_light::ColorLightState, cover::CoverState,
+    dimmable_light::DimmableLightState, fan::FanState, garage_door::GarageDoorState,
+    lock::LockState, media_player::MediaPlayerState, sensor::SensorState, switch::SwitchState,
+    thermostat::ThermostatState, tunable_white_light::TunableWhiteLightState, valve::ValveState,
 };
 use serde_derive::{Deserialize, Serialize};
 
-use crate::updates::AttributeUpdate;
+use crate::{
+    transitions::Transition,
+    updates::{AttributeUpdate, StateUpdates, UpdateBatch, UpdateCondition},
+};
+
+#[macro_use]
+mod macros;
 
+pub mod color;
 pub mod device_types;
 pub mod protocol;
+pub mod transitions;
 pub mod updates;
 
 // Macro to declare DeviceType enum with just device types and DeviceState enum that maps device types to its corresponding device state and a method to get a DeviceState's device type
@@ -27,16 +38,18 @@ macro_rules! define_device_enums {
                 $(
                     $variant,
                 )*
+                #[serde(other)]
                 Unknown
             }
 
-            #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
-            #[non_exhaustive]
-            pub enum DeviceState {
-                $(
-                    $variant([<$variant State>]),
-                )*
-                Unknown
+            define_lenient_enum! {
+                #[derive(Clone, Copy, Debug, Serialize, PartialEq)]
+                #[non_exhaustive]
+                pub enum DeviceState {
+                    $(
+                        $variant([<$variant State>]),
+                    )*
+                }
             }
 
             impl DeviceState {
@@ -52,6 +65,41 @@ macro_rules! define_device_enums {
                 pub fn is_kind(&self, kind: DeviceType) -> bool {
                     self.kind() == DeviceType::Unknown || self.kind() == kind
                 }
+
+                pub fn apply_update(&mut self, update: &AttributeUpdate) -> Result<()> {
+                    if update.is_unknown() {
+                        bail!("unsupported update from a newer peer: {:?}", update);
+                    }
+
+                    match self {
+                        $(
+                            DeviceState::$variant(state) => state.apply_update(update),
+                        )*
+                        DeviceState::Unknown => bail!("cannot apply update to unknown device state")
+                    }
+                }
+
+                pub fn check_condition(&self, condition: &UpdateCondition) -> bool {
+                    match self {
+                        $(
+                            DeviceState::$variant(state) => state.check_condition(condition),
+                        )*
+                        DeviceState::Unknown => false
+                    }
+                }
+
+                // Changed attributes as absolute updates that turn this state into other, None if the
+                // states are of different device types
+                pub fn diff(&self, other: &DeviceState) -> Option<StateUpdates> {
+                    match (self, other) {
+                        $(
+                            (DeviceState::$variant(state), DeviceState::$variant(other)) => {
+                                Some(state.diff(other))
+                            }
+                        )*
+                        _ => None
+                    }
+                }
             }
         }
     };
@@ -61,40 +109,664 @@ pub type DeviceId = ArrayString<32>;
 
 pub type SceneId = ArrayString<32>;
 
+// Channel of a device that exposes several independently controlled endpoints over one
+// connection, like a multi-gang relay board. Single-endpoint devices only use endpoint 0.
+pub type EndpointId = u8;
+
 define_device_enums! {
     Switch,
     ColorLight,
     DimmableLight,
     CeilingFan,
+    TunableWhiteLight,
+    Cover,
+    Thermostat,
+    Sensor,
+    Lock,
+    MediaPlayer,
+    Fan,
+    GarageDoor,
+    Valve,
+}
+
+impl DeviceType {
+    // Read-only devices only report state, any update sent to them is rejected
+    pub fn is_read_only(&self) -> bool {
+        matches!(self, DeviceType::Sensor)
+    }
 }
 
 // Sent from clients to server
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
 pub struct UpdateRequest {
     pub device_id: DeviceId,
+    #[serde(default)]
+    pub endpoint: EndpointId,
     pub update: AttributeUpdate,
+    #[serde(default)]
+    pub transition: Option<Transition>,
+    #[serde(default)]
+    pub condition: Option<UpdateCondition>,
 }
 
 // Sent from server to devices
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
 pub struct UpdateCommand {
     pub device_id: DeviceId,
+    #[serde(default)]
+    pub endpoint: EndpointId,
     pub update: AttributeUpdate,
+    #[serde(default)]
+    pub transition: Option<Transition>,
+    #[serde(default)]
+    pub condition: Option<UpdateCondition>,
 }
 
-impl From<UpdateRequest> for UpdateCommand {
-    fn from(request: UpdateRequest) -> Self {
-        Self {
+// Unlock requests are refused, the server can only reach a lock through an authorized UnlockCommand
+impl TryFrom<UpdateRequest> for UpdateCommand {
+    type Error = anyhow::Error;
+
+    fn try_from(request: UpdateRequest) -> Result<Self> {
+        if request.update.requires_authorization() {
+            bail!("unlock must be sent to devices as an authorized UnlockCommand");
+        }
+
+        Ok(Self {
             device_id: request.device_id,
+            endpoint: request.endpoint,
             update: request.update,
-        }
+            transition: request.transition,
+            condition: request.condition,
+        })
     }
 }
 
 // Sent from devices to server and server to clients
-#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
 pub struct UpdateNotification {
     pub device_id: DeviceId,
+    #[serde(default)]
+    pub endpoint: EndpointId,
     pub reachable: bool,
     pub new_state: DeviceState,
+    // Incremented by the device for every full or delta notification, wrapping around at u32::MAX
+    #[serde(default)]
+    pub sequence: u32,
+}
+
+// Sent from devices to server and server to clients instead of an UpdateNotification when only a
+// few attributes changed. Applying the changes to the state of the previous notification gives the
+// new state, see CachedDeviceState.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct DeltaUpdateNotification {
+    pub device_id: DeviceId,
+    #[serde(default)]
+    pub endpoint: EndpointId,
+    pub reachable: bool,
+    pub sequence: u32,
+    pub changes: UpdateBatch,
+}
+
+impl UpdateNotification {
+    // Returns None if the change from previous cannot be expressed as a delta, either because too
+    // many attributes changed or because it is not reproducible through updates, like a sensor
+    // reading. A full notification has to be sent instead.
+    pub fn to_delta(&self, previous: &DeviceState) -> Option<DeltaUpdateNotification> {
+        let mut changes = UpdateBatch::new();
+        for update in previous.diff(&self.new_state)? {
+            changes.push(update).ok()?;
+        }
+
+        let mut state = *previous;
+        state.apply_batch(&changes).ok()?;
+        if state != self.new_state {
+            return None;
+        }
+
+        Some(DeltaUpdateNotification {
+            device_id: self.device_id,
+            endpoint: self.endpoint,
+            reachable: self.reachable,
+            sequence: self.sequence,
+            changes,
+        })
+    }
+}
+
+// Receiver side copy of a device's state, kept up to date from full and delta notifications
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CachedDeviceState {
+    pub reachable: bool,
+    pub state: DeviceState,
+    pub sequence: u32,
+}
+
+impl From<&UpdateNotification> for CachedDeviceState {
+    fn from(notification: &UpdateNotification) -> Self {
+        Self {
+            reachable: notification.reachable,
+            state: notification.new_state,
+            sequence: notification.sequence,
+        }
+    }
+}
+
+impl CachedDeviceState {
+    // Full notifications always replace the cache, so they also end a resync
+    pub fn apply_notification(&mut self, notification: &UpdateNotification) {
+        *self = notification.into();
+    }
+
+    // Returns Ok(false) without touching the cache if a notification was missed, the receiver must
+    // then request the full state again with a StateQuery
+    pub fn apply_delta(&mut self, delta: &DeltaUpdateNotification) -> Result<bool> {
+        if delta.sequence != self.sequence.wrapping_add(1) {
+            return Ok(false);
+        }
+
+        self.state.apply_batch(&delta.changes)?;
+        self.reachable = delta.reachable;
+        self.sequence = delta.sequence;
+        Ok(true)
+    }
+}
+
+// Sent from clients to server, applied by the device as a single state change
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BatchUpdateRequest {
+    pub device_id: DeviceId,
+    #[serde(default)]
+    pub endpoint: EndpointId,
+    pub updates: UpdateBatch,
+    #[serde(default)]
+    pub transition: Option<Transition>,
+    #[serde(default)]
+    pub condition: Option<UpdateCondition>,
+}
+
+// Sent from server to devices, acknowledged with a single notification once every update is applied
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BatchUpdateCommand {
+    pub device_id: DeviceId,
+    #[serde(default)]
+    pub endpoint: EndpointId,
+    pub updates: UpdateBatch,
+    #[serde(default)]
+    pub transition: Option<Transition>,
+    #[serde(default)]
+    pub condition: Option<UpdateCondition>,
+}
+
+impl TryFrom<BatchUpdateRequest> for BatchUpdateCommand {
+    type Error = anyhow::Error;
+
+    fn try_from(request: BatchUpdateRequest) -> Result<Self> {
+        if request
+            .updates
+            .iter()
+            .any(AttributeUpdate::requires_authorization)
+        {
+            bail!("unlock must be sent to devices as an authorized UnlockCommand");
+        }
+
+        Ok(Self {
+            device_id: request.device_id,
+            endpoint: request.endpoint,
+            updates: request.updates,
+            transition: request.transition,
+            condition: request.condition,
+        })
+    }
+}
+
+impl DeviceState {
+    // Minimal absolute updates that bring a device from this state to target, used to replay
+    // scenes that are stored as target states
+    pub fn updates_to(&self, target: &DeviceState) -> Result<StateUpdates> {
+        if self.kind().is_read_only() {
+            bail!("cannot update read-only {:?} device", self.kind());
+        }
+
+        match self.diff(target) {
+            Some(updates) => Ok(updates),
+            None => bail!(
+                "cannot update {:?} device to a {:?} state",
+                self.kind(),
+                target.kind()
+            ),
+        }
+    }
+
+    // Applies every update in order, leaving the state untouched if any of them fails
+    pub fn apply_batch(&mut self, updates: &UpdateBatch) -> Result<()> {
+        let mut new_state = *self;
+        for update in updates.iter() {
+            new_state.apply_update(update)?;
+        }
+
+        *self = new_state;
+        Ok(())
+    }
+
+    // Returns Ok(false) without touching the state if the condition does not hold, devices should
+    // then respond with PreconditionFailed
+    pub fn apply_update_if(
+        &mut self,
+        update: &AttributeUpdate,
+        condition: Option<&UpdateCondition>,
+    ) -> Result<bool> {
+        if let Some(condition) = condition
+            && condition.is_unknown()
+        {
+            bail!("unsupported condition from a newer peer: {:?}", condition);
+        }
+
+        if condition.is_some_and(|condition| !self.check_condition(condition)) {
+            return Ok(false);
+        }
+
+        self.apply_update(update)?;
+        Ok(true)
+    }
+
+    pub fn apply_batch_if(
+        &mut self,
+        updates: &UpdateBatch,
+        condition: Option<&UpdateCondition>,
+    ) -> Result<bool> {
+        if let Some(condition) = condition
+            && condition.is_unknown()
+        {
+            bail!("unsupported condition from a newer peer: {:?}", condition);
+        }
+
+        if condition.is_some_and(|condition| !self.check_condition(condition)) {
+            return Ok(false);
+        }
+
+        self.apply_batch(updates)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::{Rgbww, Xy},
+        device_types::{
+            NumericState, NumericUnit,
+            ceiling_fan::FanDirection,
+            color_light::ColorMode,
+            cover::CoverStatus,
+            fan::{FanLightState, FanPreset},
+            garage_door::{ActuationSource, GarageDoorCommand, GarageDoorStatus},
+            lock::LockStatus,
+            media_player::{MediaControl, MediaSource, PlaybackStatus},
+            sensor::SensorReading,
+            switch::SwitchPower,
+            thermostat::{HvacAction, HvacMode},
+            valve::{ValveCommand, ValveStatus},
+        },
+        updates::NumericUpdate,
+    };
+
+    fn numeric<T: Copy + Default>(value: T, max: T, step: T) -> NumericState<T> {
+        NumericState {
+            value,
+            min: T::default(),
+            max,
+            step,
+            unit: NumericUnit::Unitless,
+        }
+    }
+
+    fn percent(value: u32) -> NumericState {
+        numeric(value, 100, 1)
+    }
+
+    fn color_temp(value: u32) -> NumericState {
+        NumericState {
+            min: 2700,
+            unit: NumericUnit::Kelvin,
+            ..numeric(value, 6500, 100)
+        }
+    }
+
+    fn absolute(value: u32) -> NumericUpdate {
+        NumericUpdate::Absolute(value)
+    }
+
+    // Builds the target by applying updates to a, then checks that the updates from a to the
+    // target turn a into it
+    fn assert_round_trip(a: DeviceState, updates: &[AttributeUpdate]) {
+        let mut b = a;
+        for update in updates {
+            b.apply_update(update).unwrap();
+        }
+
+        let mut state = a;
+        for update in &a.updates_to(&b).unwrap() {
+            state.apply_update(update).unwrap();
+        }
+        assert_eq!(state, b, "{:?} -> {:?}", a.kind(), updates);
+        assert!(b.updates_to(&b).unwrap().is_empty());
+    }
+
+    #[test]
+    fn updates_to_round_trips_every_device_type() {
+        assert_round_trip(
+            DeviceState::Switch(SwitchState {
+                power: SwitchPower::Off,
+                metering: None,
+            }),
+            &[AttributeUpdate::Power(SwitchPower::On)],
+        );
+
+        assert_round_trip(
+            DeviceState::ColorLight(ColorLightState {
+                power: SwitchPower::On,
+                brightness: percent(40),
+                hue: numeric(120, 359, 1),
+                saturation: percent(80),
+                color_mode: ColorMode::Hs,
+                xy: Some(Xy { x: 0.3, y: 0.3 }),
+                color_temp: Some(color_temp(3000)),
+                rgbww: Some(Rgbww {
+                    r: 1,
+                    g: 2,
+                    b: 3,
+                    cold_white: 4,
+                    warm_white: 5,
+                }),
+            }),
+            &[
+                AttributeUpdate::Brightness(absolute(70)),
+                AttributeUpdate::ColorTemp(absolute(4000)),
+                AttributeUpdate::Saturation(absolute(60)),
+            ],
+        );
+
+        assert_round_trip(
+            DeviceState::DimmableLight(DimmableLightState {
+                power: SwitchPower::On,
+                brightness: percent(40),
+            }),
+            &[
+                AttributeUpdate::TogglePower,
+                AttributeUpdate::Brightness(NumericUpdate::DeltaAbsolute(-15)),
+            ],
+        );
+
+        assert_round_trip(
+            DeviceState::CeilingFan(CeilingFanState {
+                fan_speed: numeric(2, 6, 1),
+                fan_direction: FanDirection::Forward,
+                light_brightness: percent(100),
+                light_color_temp: color_temp(3000),
+            }),
+            &[
+                AttributeUpdate::FanSpeed(absolute(5)),
+                AttributeUpdate::ToggleFanDirection,
+                AttributeUpdate::ColorTemp(absolute(5000)),
+            ],
+        );
+
+        assert_round_trip(
+            DeviceState::TunableWhiteLight(TunableWhiteLightState {
+                power: SwitchPower::Off,
+                brightness: percent(10),
+                color_temp: color_temp(2700),
+            }),
+            &[
+                AttributeUpdate::Power(SwitchPower::On),
+                AttributeUpdate::ColorTemp(NumericUpdate::Percent(0.5)),
+            ],
+        );
+
+        assert_round_trip(
+            DeviceState::Cover(CoverState {
+                position: percent(0),
+                tilt: Some(percent(50)),
+                status: CoverStatus::Stopped,
+            }),
+            &[
+                AttributeUpdate::CoverTilt(absolute(20)),
+                AttributeUpdate::CoverPosition(absolute(80)),
+            ],
+        );
+        assert_round_trip(
+            DeviceState::Cover(CoverState {
+                position: percent(100),
+                tilt: Some(percent(50)),
+                status: CoverStatus::Stopped,
+            }),
+            &[
+                AttributeUpdate::CoverPosition(absolute(30)),
+                AttributeUpdate::CoverTilt(absolute(0)),
+            ],
+        );
+
+        assert_round_trip(
+            DeviceState::Thermostat(ThermostatState {
+                current_temperature: 2100,
+                heat_setpoint: numeric(1800, 3000, 50),
+                cool_setpoint: numeric(2200, 3000, 50),
+                mode: HvacMode::Heat,
+                action: HvacAction::Idle,
+            }),
+            &[
+                AttributeUpdate::HvacMode(HvacMode::Auto),
+                AttributeUpdate::CoolSetpoint(NumericUpdate::Absolute(2800)),
+                AttributeUpdate::HeatSetpoint(NumericUpdate::Absolute(2500)),
+            ],
+        );
+
+        assert_round_trip(
+            DeviceState::Lock(LockState {
+                status: LockStatus::Unlocked,
+                unlock_nonce: 7,
+            }),
+            &[AttributeUpdate::Lock],
+        );
+
+        assert_round_trip(
+            DeviceState::MediaPlayer(MediaPlayerState {
+                playback: PlaybackStatus::Paused,
+                volume: percent(30),
+                muted: true,
+                source: None,
+                track: None,
+            }),
+            &[
+                AttributeUpdate::MediaControl(MediaControl::Play),
+                AttributeUpdate::Volume(absolute(45)),
+                AttributeUpdate::Mute(false),
+                AttributeUpdate::SelectSource(MediaSource::from("spotify").unwrap()),
+            ],
+        );
+
+        assert_round_trip(
+            DeviceState::Fan(FanState {
+                speed: numeric(1, 4, 1),
+                direction: Some(FanDirection::Forward),
+                oscillating: Some(false),
+                preset: Some(FanPreset::Eco),
+                light: Some(FanLightState {
+                    brightness: percent(100),
+                    color_temp: color_temp(3000),
+                }),
+            }),
+            &[
+                AttributeUpdate::FanSpeed(absolute(3)),
+                AttributeUpdate::FanPreset(Some(FanPreset::Boost)),
+                AttributeUpdate::FanOscillation(true),
+                AttributeUpdate::Brightness(absolute(20)),
+            ],
+        );
+
+        assert_round_trip(
+            DeviceState::GarageDoor(GarageDoorState {
+                status: GarageDoorStatus::Closed,
+                obstructed: false,
+                last_actuation: Some(ActuationSource::Remote),
+                interlock: false,
+            }),
+            &[AttributeUpdate::GarageDoor(GarageDoorCommand::Open)],
+        );
+
+        assert_round_trip(
+            DeviceState::Valve(ValveState {
+                status: ValveStatus::Closed,
+                remaining_secs: None,
+                flow: None,
+            }),
+            &[AttributeUpdate::Valve(ValveCommand::OpenFor(600))],
+        );
+    }
+
+    #[test]
+    fn updates_to_rejects_read_only_and_mismatched_targets() {
+        let sensor = DeviceState::Sensor(SensorState {
+            temperature: None,
+            humidity: None,
+            illuminance: None,
+            motion: None,
+            contact: None,
+            air_quality: None,
+        });
+        assert!(sensor.updates_to(&sensor).is_err());
+
+        let switch = DeviceState::Switch(SwitchState {
+            power: SwitchPower::On,
+            metering: None,
+        });
+        assert!(switch.updates_to(&sensor).is_err());
+    }
+
+    fn fan_with_light() -> DeviceState {
+        DeviceState::Fan(FanState {
+            speed: numeric(1, 4, 1),
+            direction: Some(FanDirection::Forward),
+            oscillating: Some(false),
+            preset: None,
+            light: Some(FanLightState {
+                brightness: percent(100),
+                color_temp: color_temp(3000),
+            }),
+        })
+    }
+
+    fn notification(new_state: DeviceState, sequence: u32) -> UpdateNotification {
+        UpdateNotification {
+            device_id: DeviceId::from("living-room-fan").unwrap(),
+            endpoint: 0,
+            reachable: true,
+            new_state,
+            sequence,
+        }
+    }
+
+    fn changed(state: DeviceState, updates: &[AttributeUpdate]) -> DeviceState {
+        let mut state = state;
+        for update in updates {
+            state.apply_update(update).unwrap();
+        }
+        state
+    }
+
+    #[test]
+    fn delta_round_trips_through_cache() {
+        let mut cache = CachedDeviceState::from(&notification(fan_with_light(), 41));
+
+        let new_state = changed(
+            fan_with_light(),
+            &[
+                AttributeUpdate::FanSpeed(absolute(3)),
+                AttributeUpdate::Brightness(absolute(40)),
+            ],
+        );
+        let delta = notification(new_state, 42)
+            .to_delta(&fan_with_light())
+            .unwrap();
+        assert_eq!(delta.changes.iter().count(), 2);
+
+        assert!(cache.apply_delta(&delta).unwrap());
+        assert_eq!(cache, CachedDeviceState::from(&notification(new_state, 42)));
+    }
+
+    #[test]
+    fn skipped_sequence_leaves_cache_untouched() {
+        let mut cache = CachedDeviceState::from(&notification(fan_with_light(), 41));
+        let before = cache;
+
+        let new_state = changed(fan_with_light(), &[AttributeUpdate::FanSpeed(absolute(3))]);
+        let delta = notification(new_state, 43)
+            .to_delta(&fan_with_light())
+            .unwrap();
+
+        assert!(!cache.apply_delta(&delta).unwrap());
+        assert_eq!(cache, before);
+
+        // A replayed notification is a skip as well
+        let delta = notification(new_state, 41)
+            .to_delta(&fan_with_light())
+            .unwrap();
+        assert!(!cache.apply_delta(&delta).unwrap());
+        assert_eq!(cache, before);
+    }
+
+    #[test]
+    fn sequence_wraps_around() {
+        let mut cache = CachedDeviceState::from(&notification(fan_with_light(), u32::MAX));
+
+        let new_state = changed(fan_with_light(), &[AttributeUpdate::FanOscillation(true)]);
+        let delta = notification(new_state, 0)
+            .to_delta(&fan_with_light())
+            .unwrap();
+
+        assert!(cache.apply_delta(&delta).unwrap());
+        assert_eq!(cache.sequence, 0);
+        assert_eq!(cache.state, new_state);
+    }
+
+    #[test]
+    fn too_many_changes_need_a_full_notification() {
+        let new_state = changed(
+            fan_with_light(),
+            &[
+                AttributeUpdate::FanSpeed(absolute(3)),
+                AttributeUpdate::FanDirection(FanDirection::Reverse),
+                AttributeUpdate::FanOscillation(true),
+                AttributeUpdate::Brightness(absolute(40)),
+                AttributeUpdate::ColorTemp(absolute(5000)),
+            ],
+        );
+        assert_eq!(fan_with_light().diff(&new_state).unwrap().len(), 5);
+        assert!(
+            notification(new_state, 42)
+                .to_delta(&fan_with_light())
+                .is_none()
+        );
+
+        // Sensor readings have no updates to express them
+        let sensor = |temperature| {
+            DeviceState::Sensor(SensorState {
+                temperature: Some(SensorReading {
+                    value: temperature,
+                    timestamp: None,
+                }),
+                humidity: None,
+                illuminance: None,
+                motion: None,
+                contact: None,
+                air_quality: None,
+            })
+        };
+        assert!(
+            notification(sensor(2150), 1)
+                .to_delta(&sensor(2100))
+                .is_none()
+        );
+    }
 }
@@ -0,0 +1,124 @@
+// Macro to declare an externally tagged enum whose Deserialize maps any variant it does not know
+// to an added Unknown variant instead of failing, so older peers keep working as variants are
+// added. Variants are either units or newtypes. Works without alloc by skipping unknown values.
+// Type parameters take a single bound and a default, like NumericUpdate<T: Copy = u32>.
+macro_rules! define_lenient_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident $(< $($param:ident : $bound:ident = $default:ty),+ >)? {
+            $(
+                $variant:ident $(($ty:ty))?
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis enum $name $(< $($param: $bound = $default),+ >)? {
+            $(
+                $variant $(($ty))?,
+            )*
+            Unknown
+        }
+
+        impl<'de $($(, $param: $bound + serde::Deserialize<'de>)+)?> serde::Deserialize<'de>
+            for $name $(< $($param),+ >)?
+        {
+            fn deserialize<__D: serde::Deserializer<'de>>(deserializer: __D) -> Result<Self, __D::Error> {
+                use core::fmt;
+                use serde::de::{self, IgnoredAny, MapAccess, Visitor};
+
+                #[allow(non_camel_case_types)]
+                enum Tag {
+                    $(
+                        $variant,
+                    )*
+                    __Unknown
+                }
+
+                struct TagVisitor;
+
+                impl<'de> Visitor<'de> for TagVisitor {
+                    type Value = Tag;
+
+                    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                        f.write_str("variant identifier")
+                    }
+
+                    fn visit_str<E: de::Error>(self, tag: &str) -> Result<Tag, E> {
+                        Ok(match tag {
+                            $(
+                                stringify!($variant) => Tag::$variant,
+                            )*
+                            _ => Tag::__Unknown,
+                        })
+                    }
+                }
+
+                impl<'de> serde::Deserialize<'de> for Tag {
+                    fn deserialize<__D: serde::Deserializer<'de>>(deserializer: __D) -> Result<Self, __D::Error> {
+                        deserializer.deserialize_identifier(TagVisitor)
+                    }
+                }
+
+                struct EnumVisitor $(< $($param),+ >)? (
+                    core::marker::PhantomData<($($($param,)+)?)>,
+                );
+
+                impl<'de $($(, $param: $bound + serde::Deserialize<'de>)+)?> Visitor<'de>
+                    for EnumVisitor $(< $($param),+ >)?
+                {
+                    type Value = $name $(< $($param),+ >)?;
+
+                    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                        f.write_str(concat!("enum ", stringify!($name)))
+                    }
+
+                    // Unit variants are a bare string
+                    fn visit_str<E: de::Error>(self, tag: &str) -> Result<Self::Value, E> {
+                        match TagVisitor.visit_str::<E>(tag)? {
+                            $(
+                                Tag::$variant => define_lenient_enum!(@unit self $name $variant $($ty)?),
+                            )*
+                            Tag::__Unknown => Ok($name::Unknown),
+                        }
+                    }
+
+                    // Newtype variants are a single entry map of { variant: value }
+                    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                        let tag = map
+                            .next_key::<Tag>()?
+                            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+
+                        Ok(match tag {
+                            $(
+                                Tag::$variant => define_lenient_enum!(@value map $name $variant $($ty)?),
+                            )*
+                            Tag::__Unknown => {
+                                map.next_value::<IgnoredAny>()?;
+                                $name::Unknown
+                            }
+                        })
+                    }
+                }
+
+                deserializer.deserialize_any(EnumVisitor(core::marker::PhantomData))
+            }
+        }
+    };
+
+    (@unit $visitor:ident $name:ident $variant:ident $ty:ty) => {
+        Err(serde::de::Error::invalid_type(
+            serde::de::Unexpected::UnitVariant,
+            &$visitor,
+        ))
+    };
+    (@unit $visitor:ident $name:ident $variant:ident) => {
+        Ok($name::$variant)
+    };
+    (@value $map:ident $name:ident $variant:ident $ty:ty) => {
+        $name::$variant($map.next_value::<$ty>()?)
+    };
+    (@value $map:ident $name:ident $variant:ident) => {{
+        $map.next_value::<serde::de::IgnoredAny>()?;
+        $name::$variant
+    }};
+}
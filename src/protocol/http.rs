@@ -7,22 +7,24 @@ extern crate alloc;
 #[cfg(feature = "alloc")]
 use alloc::string::ToString;
 
-use crate::{SceneId, UpdateRequest};
+use crate::{BatchUpdateRequest, SceneId, UpdateRequest};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[non_exhaustive]
 pub enum ServerBoundHttpMessage {
     UpdateRequest(UpdateRequest),
+    BatchUpdateRequest(BatchUpdateRequest),
     ActivateScene(SceneId),
 }
 
 pub type FailureMessage = ArrayString<100>;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[non_exhaustive]
 pub enum ClientBoundHttpMessage {
     Unimplemented,
     RequestReceived,
+    PreconditionFailed,
     Failure(Option<FailureMessage>),
 }
 
@@ -7,17 +7,25 @@ extern crate alloc;
 #[cfg(feature = "alloc")]
 use alloc::string::ToString;
 
-use crate::DeviceId;
-use crate::{UpdateCommand, UpdateNotification};
+use crate::device_types::lock::UnlockCommand;
+use crate::{BatchUpdateCommand, DeltaUpdateNotification, UpdateCommand, UpdateNotification};
+use crate::{DeviceId, EndpointId};
 
 pub type FailureMessage = ArrayString<100>;
 
 // Message sent from server to devices
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[non_exhaustive]
 pub enum DeviceBoundKryptonMessage {
     UpdateCommand(UpdateCommand),
-    StateQuery { device_id: DeviceId },
+    BatchUpdateCommand(BatchUpdateCommand),
+    UnlockCommand(UnlockCommand),
+    // Endpoint None queries every endpoint of the device
+    StateQuery {
+        device_id: DeviceId,
+        #[serde(default)]
+        endpoint: Option<EndpointId>,
+    },
     Failure(Option<FailureMessage>),
 }
 
@@ -35,12 +43,14 @@ impl From<anyhow::Error> for DeviceBoundKryptonMessage {
 }
 
 // Message sent from devices to server
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[non_exhaustive]
 pub enum ServerBoundKryptonMessage {
     Identify(DeviceId),
     RequestReceived,
+    PreconditionFailed,
     UpdateNotification(UpdateNotification),
+    DeltaUpdateNotification(DeltaUpdateNotification),
     Failure(Option<FailureMessage>),
 }
 
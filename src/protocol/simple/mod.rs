@@ -7,8 +7,9 @@ extern crate alloc;
 #[cfg(feature = "alloc")]
 use alloc::string::ToString;
 
-use crate::DeviceId;
-use crate::{UpdateCommand, UpdateNotification};
+use crate::device_types::lock::UnlockCommand;
+use crate::{BatchUpdateCommand, DeltaUpdateNotification, UpdateCommand, UpdateNotification};
+use crate::{DeviceId, EndpointId};
 
 #[cfg(feature = "tokio")]
 pub mod tokio;
@@ -23,11 +24,18 @@ pub const SIGNATURE_LEN: usize = 64;
 pub type FailureMessage = ArrayString<100>;
 
 // Message sent from server to devices
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[non_exhaustive]
 pub enum DeviceBoundSimpleMessage {
     UpdateCommand(UpdateCommand),
-    StateQuery { device_id: DeviceId },
+    BatchUpdateCommand(BatchUpdateCommand),
+    UnlockCommand(UnlockCommand),
+    // Endpoint None queries every endpoint of the device
+    StateQuery {
+        device_id: DeviceId,
+        #[serde(default)]
+        endpoint: Option<EndpointId>,
+    },
     Failure(Option<FailureMessage>),
 }
 
@@ -45,12 +53,14 @@ impl From<anyhow::Error> for DeviceBoundSimpleMessage {
 }
 
 // Message sent from devices to server
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[non_exhaustive]
 pub enum ServerBoundSimpleMessage {
     Identify(DeviceId),
     RequestReceived,
+    PreconditionFailed,
     UpdateNotification(UpdateNotification),
+    DeltaUpdateNotification(DeltaUpdateNotification),
     Failure(Option<FailureMessage>),
 }
 
@@ -7,24 +7,35 @@ extern crate alloc;
 #[cfg(feature = "alloc")]
 use alloc::string::ToString;
 
-use crate::{DeviceId, SceneId, UpdateNotification, UpdateRequest};
+use crate::{
+    BatchUpdateRequest, DeltaUpdateNotification, DeviceId, EndpointId, SceneId, UpdateNotification,
+    UpdateRequest,
+};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[non_exhaustive]
 pub enum ServerBoundSocketMessage {
     UpdateRequest(UpdateRequest),
+    BatchUpdateRequest(BatchUpdateRequest),
     ActivateScene(SceneId),
-    StateQuery { device_id: DeviceId },
+    // Endpoint None queries every endpoint of the device
+    StateQuery {
+        device_id: DeviceId,
+        #[serde(default)]
+        endpoint: Option<EndpointId>,
+    },
 }
 
 pub type FailureMessage = ArrayString<100>;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[non_exhaustive]
 pub enum ClientBoundSocketMessage {
     Unimplemented,
     RequestReceived,
+    PreconditionFailed,
     UpdateNotification(UpdateNotification),
+    DeltaUpdateNotification(DeltaUpdateNotification),
     Failure(Option<FailureMessage>),
 }
 
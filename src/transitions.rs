@@ -0,0 +1,88 @@
+use num_traits::{NumCast, cast, float::FloatCore};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::device_types::NumericState;
+
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Easing {
+    #[default]
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl Easing {
+    // Maps linear progress in [0, 1] to eased progress in [0, 1]
+    pub fn apply(&self, progress: f32) -> f32 {
+        let t = progress.clamp(0.0, 1.0);
+
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut if t < 0.5 => 2.0 * t * t,
+            Easing::EaseInOut => -1.0 + (4.0 - 2.0 * t) * t,
+        }
+    }
+}
+
+// Optional fade attached to an update, applied by the device
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct Transition {
+    pub duration_ms: u32,
+    #[serde(default)]
+    pub easing: Easing,
+}
+
+// A running fade of a single numeric attribute. Devices tick this from a timer with the time
+// elapsed since the fade started to get the intermediate state to apply to the hardware.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NumericTransition<T: Copy = u32> {
+    pub start: NumericState<T>,
+    pub target: T,
+    pub transition: Transition,
+}
+
+impl<T: Copy + Ord + NumCast> NumericTransition<T> {
+    pub fn new(start: NumericState<T>, target: T, transition: Transition) -> Self {
+        Self {
+            start,
+            target,
+            transition,
+        }
+    }
+
+    pub fn is_finished(&self, elapsed_ms: u32) -> bool {
+        elapsed_ms >= self.transition.duration_ms
+    }
+
+    pub fn value_at(&self, elapsed_ms: u32) -> T {
+        if self.is_finished(elapsed_ms) {
+            return self.target;
+        }
+
+        let (Some(from), Some(to)) = (
+            cast::<T, f64>(self.start.value),
+            cast::<T, f64>(self.target),
+        ) else {
+            return self.target;
+        };
+
+        let progress = self
+            .transition
+            .easing
+            .apply(elapsed_ms as f32 / self.transition.duration_ms as f32);
+
+        // Intermediate values are not snapped to the step so fades stay smooth
+        cast::<f64, T>(FloatCore::round(from + (to - from) * progress as f64))
+            .unwrap_or(self.target)
+    }
+
+    pub fn state_at(&self, elapsed_ms: u32) -> NumericState<T> {
+        NumericState {
+            value: self.value_at(elapsed_ms),
+            ..self.start
+        }
+    }
+}
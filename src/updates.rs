@@ -1,67 +1,579 @@
-use core::ops::{Add, Mul, Sub};
-use num_traits::{NumCast, cast};
+use arrayvec::ArrayVec;
+use num_traits::{NumCast, cast, float::FloatCore};
 use serde_derive::{Deserialize, Serialize};
 
-use crate::device_types::{NumericState, ceiling_fan::FanDirection, switch::SwitchPower};
+use crate::{
+    color::{Rgb, Rgbww, Xy},
+    device_types::{
+        NumericState,
+        ceiling_fan::FanDirection,
+        fan::FanPreset,
+        garage_door::{GarageDoorCommand, GarageDoorStatus},
+        lock::LockStatus,
+        media_player::{MediaControl, MediaSource, PlaybackStatus},
+        switch::SwitchPower,
+        thermostat::{CentiCelsius, HvacMode},
+        valve::{ValveCommand, ValveStatus},
+    },
+};
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
-pub enum NumericUpdate<T: Copy = u32, F: Copy = f32> {
-    Percent(F),
-    Absolute(T),
-    DeltaAbsolute(T), // add value
-    DeltaPercent(F),  // add percentage
-    ScaleBy(F),       // multiply by value
+// Percentages are fractions of the full max - min span (1.0 = 100%) and may be negative.
+// Deltas use their own signed type D so unsigned attributes can also be decreased.
+define_lenient_enum! {
+    #[derive(Copy, Clone, Debug, Serialize, PartialEq)]
+    #[non_exhaustive]
+    pub enum NumericUpdate<T: Copy = u32, F: Copy = f32, D: Copy = i64> {
+        Percent(F), // set to min + percentage of the span
+        Absolute(T),
+        DeltaAbsolute(D), // add value, saturating at min/max
+        DeltaPercent(F),  // add percentage of the span
+        ScaleBy(F),       // multiply by value
+        Cycle(D),         // move this many steps along the step grid, wrapping around at min/max
+    }
+}
+
+// How a computed value is snapped onto the min + k * step grid of a NumericState
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum Rounding {
+    #[default]
+    Nearest,
+    Up,
+    Down,
+    TowardChange, // up when the value increases, down when it decreases
+}
+
+impl<T: Copy, F: Copy, D: Copy> NumericUpdate<T, F, D> {
+    pub fn is_unknown(&self) -> bool {
+        matches!(self, NumericUpdate::Unknown)
+    }
 }
 
 // Helper function to apply a NumericUpdate to a current value
-impl<T, F> NumericUpdate<T, F>
+impl<T, F, D> NumericUpdate<T, F, D>
 where
-    T: Copy + Default + Ord + NumCast + Add<Output = T> + Sub<Output = T>,
-    F: Copy + Default + NumCast + Mul<Output = F>,
+    T: Copy + Ord + NumCast,
+    F: Copy + NumCast,
+    D: Copy + NumCast,
 {
+    // Converts the value type of Absolute updates, used when an update's type differs from the state
+    pub fn cast<U: Copy + NumCast>(&self) -> Option<NumericUpdate<U, F, D>> {
+        Some(match *self {
+            NumericUpdate::Percent(pct) => NumericUpdate::Percent(pct),
+            NumericUpdate::Absolute(new_value) => NumericUpdate::Absolute(cast(new_value)?),
+            NumericUpdate::DeltaAbsolute(delta) => NumericUpdate::DeltaAbsolute(delta),
+            NumericUpdate::DeltaPercent(pct) => NumericUpdate::DeltaPercent(pct),
+            NumericUpdate::ScaleBy(factor) => NumericUpdate::ScaleBy(factor),
+            NumericUpdate::Cycle(steps) => NumericUpdate::Cycle(steps),
+            NumericUpdate::Unknown => NumericUpdate::Unknown,
+        })
+    }
+
     pub fn apply_to(&self, state: &NumericState<T>) -> T {
-        // clamp but without the assert that min <= max
-        fn clamp<T: PartialOrd>(input: T, min: T, max: T) -> T {
-            if input < min {
-                min
-            } else if input > max {
-                max
-            } else {
-                input
+        self.apply_quantized(state, Rounding::Nearest)
+    }
+
+    // Same as apply_to, but the result is snapped to min + k * step using the given rounding mode.
+    // A step of zero is treated as one. Delta updates always move at least one step unless they
+    // are already at the bound in their direction.
+    pub fn apply_quantized(&self, state: &NumericState<T>, rounding: Rounding) -> T {
+        if let NumericUpdate::Absolute(new_value) = self
+            && let Some(snapped) = Self::snap_exact(*new_value, state, rounding)
+        {
+            return snapped;
+        }
+
+        // Everything else happens in f64 so it can never overflow T. Values of 64-bit types beyond
+        // 2^53 lose precision and may land on a neighbouring value.
+        fn to_f64<A: NumCast>(value: A) -> f64 {
+            cast::<A, f64>(value).unwrap_or(f64::NAN)
+        }
+
+        let value = to_f64(state.value);
+        let min = to_f64(state.min);
+        let max = to_f64(state.max);
+        let step = match to_f64(state.step) {
+            step if step > 0.0 => step,
+            _ => 1.0,
+        };
+
+        let mut target = match self {
+            NumericUpdate::Percent(pct) => min + to_f64(*pct) * (max - min),
+            NumericUpdate::Absolute(new_value) => to_f64(*new_value),
+            NumericUpdate::DeltaAbsolute(delta) => value + to_f64(*delta),
+            NumericUpdate::DeltaPercent(pct) => value + to_f64(*pct) * (max - min),
+            NumericUpdate::ScaleBy(factor) => to_f64(*factor) * value,
+            NumericUpdate::Cycle(steps) => {
+                // Number of grid points between min and max, inclusive
+                let points = FloatCore::floor((max - min) / step) + 1.0;
+                let index = (FloatCore::round((value - min) / step) + to_f64(*steps)) % points;
+                min + if index < 0.0 { index + points } else { index } * step
             }
+            // Rejected by DeviceState::apply_update, only reached when a state is updated directly
+            NumericUpdate::Unknown => value,
+        };
+
+        // Undefined results (NaN percentages, inf * 0) leave the value where it is
+        if target.is_nan() {
+            target = value;
         }
 
-        // infallible cast with default fallback
-        fn safe_cast<A: NumCast, B: NumCast + Default>(value: A) -> B {
-            cast::<A, B>(value).unwrap_or_default()
+        // Position of the target on the step grid
+        let index = (target - min) / step;
+        let mut index = match rounding {
+            Rounding::Nearest => FloatCore::round(index),
+            Rounding::Up => FloatCore::ceil(index),
+            Rounding::Down => FloatCore::floor(index),
+            Rounding::TowardChange if target > value => FloatCore::ceil(index),
+            Rounding::TowardChange => FloatCore::floor(index),
+        };
+
+        if matches!(
+            self,
+            NumericUpdate::DeltaAbsolute(_) | NumericUpdate::DeltaPercent(_)
+        ) {
+            let current_index = (value - min) / step;
+            if target > value && index <= current_index {
+                index = FloatCore::floor(current_index) + 1.0;
+            } else if target < value && index >= current_index {
+                index = FloatCore::ceil(current_index) - 1.0;
+            }
         }
 
-        clamp(
-            match self {
-                NumericUpdate::Percent(pct) => {
-                    state.min + safe_cast(*pct * safe_cast(state.max - state.min))
-                }
-                NumericUpdate::Absolute(new_value) => *new_value,
-                NumericUpdate::DeltaAbsolute(delta) => state.value + *delta,
-                NumericUpdate::DeltaPercent(pct) => {
-                    state.value + safe_cast(*pct * safe_cast(state.value - state.min))
-                }
-                NumericUpdate::ScaleBy(factor) => safe_cast(*factor * safe_cast(state.value)),
-            },
-            state.min,
-            state.max,
-        )
+        let snapped = min + index * step;
+
+        // clamp but without the assert that min <= max, returning the bounds themselves so
+        // they never need to round-trip through f64. A bound of a 64-bit type can round past the
+        // end of T in f64, the cast then fails and the value snaps to that bound.
+        if snapped < min {
+            state.min
+        } else if snapped > max {
+            state.max
+        } else {
+            cast::<f64, T>(snapped).unwrap_or(if snapped >= max { state.max } else { state.min })
+        }
+    }
+
+    // Snaps an absolute value in i128 so 64-bit values are exact, the same way as the f64 path.
+    // None if a value does not fit in i128 or the bounds are inverted.
+    fn snap_exact(target: T, state: &NumericState<T>, rounding: Rounding) -> Option<T> {
+        let value = cast::<T, i128>(state.value)?;
+        let target = cast::<T, i128>(target)?;
+        let min = cast::<T, i128>(state.min)?;
+        let max = cast::<T, i128>(state.max)?;
+        let step = cast::<T, i128>(state.step)?.max(1);
+
+        if min > max {
+            return None;
+        }
+        if target <= min {
+            return Some(state.min);
+        }
+        if target >= max {
+            return Some(state.max);
+        }
+
+        let remainder = (target - min) % step;
+        if remainder == 0 {
+            return cast(target);
+        }
+
+        let down = target - remainder;
+        let up = down + step;
+        let snapped = match rounding {
+            Rounding::Nearest if remainder * 2 >= step => up,
+            Rounding::Nearest => down,
+            Rounding::Up => up,
+            Rounding::Down => down,
+            Rounding::TowardChange if target > value => up,
+            Rounding::TowardChange => down,
+        };
+
+        if snapped > max {
+            Some(state.max)
+        } else {
+            cast(snapped)
+        }
+    }
+}
+
+// Precondition on a device's current state that must hold for a conditional update to be applied
+define_lenient_enum! {
+    #[derive(Copy, Clone, Debug, Serialize, PartialEq, Eq, Hash)]
+    #[non_exhaustive]
+    pub enum UpdateCondition {
+        Power(SwitchPower),
+        Brightness(u32),
+        ColorTemp(u32),
+        Hue(u32),
+        Saturation(u32),
+        FanSpeed(u32),
+        FanDirection(FanDirection),
+        CoverPosition(u32),
+        HvacMode(HvacMode),
+        LockStatus(LockStatus),
+        Playback(PlaybackStatus),
+        GarageDoorStatus(GarageDoorStatus),
+        ValveStatus(ValveStatus),
+    }
+}
+
+impl UpdateCondition {
+    // Conditions from a newer peer cannot be checked, devices reject them instead of failing the
+    // precondition
+    pub fn is_unknown(&self) -> bool {
+        matches!(self, UpdateCondition::Unknown)
+    }
+}
+
+define_lenient_enum! {
+    #[derive(Copy, Clone, Debug, Serialize, PartialEq)]
+    #[non_exhaustive]
+    pub enum AttributeUpdate {
+        Power(SwitchPower),
+        Brightness(NumericUpdate),
+        ColorTemp(NumericUpdate), // in the unit of the device's NumericState, see NumericUnit
+        Hue(NumericUpdate<u16>),
+        Saturation(NumericUpdate),
+        FanSpeed(NumericUpdate),
+        FanDirection(FanDirection),
+        TogglePower,
+        ToggleFanDirection,
+        ColorRgb(Rgb), // sets hue and saturation, brightness is left unchanged
+        ColorXy(Xy),
+        ColorRgbww(Rgbww),
+        CoverPosition(NumericUpdate),
+        CoverTilt(NumericUpdate),
+        CoverStop,
+        HeatSetpoint(NumericUpdate<CentiCelsius>),
+        CoolSetpoint(NumericUpdate<CentiCelsius>),
+        HvacMode(HvacMode),
+        Lock,
+        Unlock, // requested by clients, the server must send devices an authorized UnlockCommand
+        MediaControl(MediaControl),
+        Volume(NumericUpdate),
+        Mute(bool),
+        SelectSource(MediaSource),
+        FanOscillation(bool),
+        FanPreset(Option<FanPreset>), // None returns to plain speed control
+        GarageDoor(GarageDoorCommand),
+        Valve(ValveCommand),
+    }
+}
+
+impl AttributeUpdate {
+    // True if the update or its numeric update is a variant from a newer peer, devices reject these
+    pub fn is_unknown(&self) -> bool {
+        match self {
+            AttributeUpdate::Brightness(update)
+            | AttributeUpdate::ColorTemp(update)
+            | AttributeUpdate::Saturation(update)
+            | AttributeUpdate::FanSpeed(update)
+            | AttributeUpdate::CoverPosition(update)
+            | AttributeUpdate::CoverTilt(update)
+            | AttributeUpdate::Volume(update) => update.is_unknown(),
+            AttributeUpdate::Hue(update) => update.is_unknown(),
+            AttributeUpdate::HeatSetpoint(update) | AttributeUpdate::CoolSetpoint(update) => {
+                update.is_unknown()
+            }
+            AttributeUpdate::Unknown => true,
+            _ => false,
+        }
+    }
+
+    // Updates that devices only accept through a signed command, see UnlockCommand
+    pub fn requires_authorization(&self) -> bool {
+        matches!(self, AttributeUpdate::Unlock)
+    }
+}
+
+// Enough for every attribute of the largest device state
+pub const MAX_STATE_UPDATES: usize = 8;
+
+// Updates produced by diffing two states, applying them in order turns one state into the other
+pub type StateUpdates = ArrayVec<AttributeUpdate, MAX_STATE_UPDATES>;
+
+pub const MAX_BATCH_UPDATES: usize = 4;
+
+// Bounded list of updates applied to a device as a single state change. Updates are applied in
+// order, so a later update to the same attribute wins. Serialized as a plain list.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(
+    from = "ArrayVec<AttributeUpdate, MAX_BATCH_UPDATES>",
+    into = "ArrayVec<AttributeUpdate, MAX_BATCH_UPDATES>"
+)]
+pub struct UpdateBatch {
+    // Filled from the front, ArrayVec is not Copy
+    updates: [Option<AttributeUpdate>; MAX_BATCH_UPDATES],
+}
+
+impl UpdateBatch {
+    pub const fn new() -> Self {
+        Self {
+            updates: [None; MAX_BATCH_UPDATES],
+        }
+    }
+
+    // Returns the update back if the batch is full
+    pub fn push(&mut self, update: AttributeUpdate) -> Result<(), AttributeUpdate> {
+        match self.updates.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(update);
+                Ok(())
+            }
+            None => Err(update),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &AttributeUpdate> {
+        self.updates.iter().map_while(Option::as_ref)
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.updates[0].is_none()
+    }
+
+    // Splits updates into as few batches as possible, keeping their order. Each batch is applied
+    // atomically, but a failing batch does not roll back the ones before it.
+    pub fn split(updates: &[AttributeUpdate]) -> impl Iterator<Item = UpdateBatch> {
+        updates.chunks(MAX_BATCH_UPDATES).map(|chunk| {
+            chunk
+                .iter()
+                .copied()
+                .collect::<ArrayVec<_, MAX_BATCH_UPDATES>>()
+                .into()
+        })
     }
 }
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
-#[non_exhaustive]
-pub enum AttributeUpdate {
-    Power(SwitchPower),
-    Brightness(NumericUpdate),
-    ColorTemp(NumericUpdate),
-    Hue(NumericUpdate<u16>),
-    Saturation(NumericUpdate),
-    FanSpeed(NumericUpdate),
-    FanDirection(FanDirection),
+impl Default for UpdateBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<ArrayVec<AttributeUpdate, MAX_BATCH_UPDATES>> for UpdateBatch {
+    fn from(updates: ArrayVec<AttributeUpdate, MAX_BATCH_UPDATES>) -> Self {
+        let mut batch = Self::new();
+        for (slot, update) in batch.updates.iter_mut().zip(updates) {
+            *slot = Some(update);
+        }
+        batch
+    }
+}
+
+impl From<UpdateBatch> for ArrayVec<AttributeUpdate, MAX_BATCH_UPDATES> {
+    fn from(batch: UpdateBatch) -> Self {
+        batch.iter().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::fmt::Debug;
+
+    use num_traits::Bounded;
+
+    use super::*;
+    use crate::device_types::NumericUnit;
+
+    const CASES: usize = 200_000;
+
+    // splitmix64, seeded so failures reproduce
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = self.0;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        }
+
+        // Edge cases half of the time, otherwise any bit pattern
+        fn pick<A: Copy>(&mut self, edges: &[A], random: impl FnOnce(u64) -> A) -> A {
+            let bits = self.next();
+            match bits % 2 {
+                0 => edges[(bits >> 1) as usize % edges.len()],
+                _ => random(self.next()),
+            }
+        }
+
+        fn int<T: Copy + Bounded + NumCast>(&mut self, random: impl FnOnce(u64) -> T) -> T {
+            let edges = [
+                T::min_value(),
+                T::max_value(),
+                cast(0).unwrap(),
+                cast(1).unwrap(),
+                cast(2).unwrap(),
+            ];
+            self.pick(&edges, random)
+        }
+
+        fn f32(&mut self) -> f32 {
+            let edges = [
+                f32::NAN,
+                f32::INFINITY,
+                f32::NEG_INFINITY,
+                0.0,
+                -0.0,
+                0.5,
+                1.0,
+                -1.0,
+                f32::MAX,
+                f32::MIN,
+                f32::MIN_POSITIVE,
+            ];
+            self.pick(&edges, |bits| f32::from_bits(bits as u32))
+        }
+
+        fn f64(&mut self) -> f64 {
+            let edges = [
+                f64::NAN,
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+                0.0,
+                -0.0,
+                0.5,
+                1.0,
+                -1.0,
+                f64::MAX,
+                f64::MIN,
+                f64::MIN_POSITIVE,
+            ];
+            self.pick(&edges, f64::from_bits)
+        }
+
+        fn rounding(&mut self) -> Rounding {
+            match self.next() % 4 {
+                0 => Rounding::Nearest,
+                1 => Rounding::Up,
+                2 => Rounding::Down,
+                _ => Rounding::TowardChange,
+            }
+        }
+
+        fn update<T: Copy, F: Copy, D: Copy>(
+            &mut self,
+            value: impl FnOnce(&mut Self) -> T,
+            float: impl FnOnce(&mut Self) -> F,
+            delta: impl FnOnce(&mut Self) -> D,
+        ) -> NumericUpdate<T, F, D> {
+            match self.next() % 7 {
+                0 => NumericUpdate::Percent(float(self)),
+                1 => NumericUpdate::Absolute(value(self)),
+                2 => NumericUpdate::DeltaAbsolute(delta(self)),
+                3 => NumericUpdate::DeltaPercent(float(self)),
+                4 => NumericUpdate::ScaleBy(float(self)),
+                5 => NumericUpdate::Cycle(delta(self)),
+                _ => NumericUpdate::Unknown,
+            }
+        }
+    }
+
+    // The result must never leave [min, max], with min > max it can only be one of the bounds
+    fn assert_in_bounds<T, F, D>(
+        update: &NumericUpdate<T, F, D>,
+        state: &NumericState<T>,
+        rounding: Rounding,
+    ) where
+        T: Copy + Ord + NumCast + Debug,
+        F: Copy + NumCast + Debug,
+        D: Copy + NumCast + Debug,
+    {
+        let result = update.apply_quantized(state, rounding);
+        let in_bounds = if state.min <= state.max {
+            state.min <= result && result <= state.max
+        } else {
+            result == state.min || result == state.max
+        };
+        assert!(
+            in_bounds,
+            "{:?} on {:?} with {:?} gave {:?}",
+            update, state, rounding, result
+        );
+    }
+
+    macro_rules! bounds_property {
+        ($name:ident, $t:ty, $f:ident, $d:ty, $seed:expr) => {
+            #[test]
+            fn $name() {
+                let mut rng = Rng($seed);
+                for _ in 0..CASES {
+                    let int = |rng: &mut Rng| rng.int(|bits| bits as $t);
+                    let state = NumericState {
+                        value: int(&mut rng),
+                        min: int(&mut rng),
+                        max: int(&mut rng),
+                        step: int(&mut rng),
+                        unit: NumericUnit::Unitless,
+                    };
+                    let update: NumericUpdate<$t, _, $d> = rng.update(
+                        |rng| rng.int(|bits| bits as $t),
+                        Rng::$f,
+                        |rng| rng.int(|bits| bits as $d),
+                    );
+                    let rounding = rng.rounding();
+                    assert_in_bounds(&update, &state, rounding);
+                }
+            }
+        };
+    }
+
+    bounds_property!(u16_stays_in_bounds, u16, f32, i64, 0x16);
+    bounds_property!(u32_stays_in_bounds, u32, f32, i64, 0x32);
+    bounds_property!(u16_f64_i32_stays_in_bounds, u16, f64, i32, 0x1664);
+    bounds_property!(u32_f64_i32_stays_in_bounds, u32, f64, i32, 0x3264);
+    bounds_property!(i32_stays_in_bounds, i32, f32, i64, 0x132);
+
+    #[test]
+    fn absolute_64_bit_values_are_exact() {
+        let state = NumericState {
+            value: 0,
+            min: 0,
+            max: u64::MAX,
+            step: 1,
+            unit: NumericUnit::Unitless,
+        };
+        let update = NumericUpdate::<u64>::Absolute(u64::MAX - 1);
+        assert_eq!(update.apply_to(&state), u64::MAX - 1);
+
+        let state = NumericState {
+            value: 0,
+            min: i64::MIN,
+            max: i64::MAX,
+            step: 1,
+            unit: NumericUnit::Unitless,
+        };
+        let update = NumericUpdate::<i64>::Absolute((1 << 53) + 1);
+        assert_eq!(update.apply_to(&state), (1 << 53) + 1);
+    }
+
+    #[test]
+    fn absolute_snaps_like_other_updates() {
+        let state = NumericState {
+            value: 20,
+            min: 5,
+            max: 98,
+            step: 10,
+            unit: NumericUnit::Unitless,
+        };
+        let absolute = |value| NumericUpdate::<u32>::Absolute(value);
+        assert_eq!(absolute(39).apply_to(&state), 35);
+        assert_eq!(absolute(40).apply_to(&state), 45);
+        assert_eq!(absolute(39).apply_quantized(&state, Rounding::Down), 35);
+        assert_eq!(absolute(36).apply_quantized(&state, Rounding::Up), 45);
+        assert_eq!(
+            absolute(12).apply_quantized(&state, Rounding::TowardChange),
+            5
+        );
+        assert_eq!(absolute(97).apply_to(&state), 95);
+        assert_eq!(absolute(96).apply_quantized(&state, Rounding::Up), 98);
+        assert_eq!(absolute(200).apply_quantized(&state, Rounding::Down), 98);
+        assert_eq!(absolute(0).apply_to(&state), 5);
+    }
 }